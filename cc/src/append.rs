@@ -0,0 +1,97 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use crate::wrapper;
+
+/// A compiler invocation to append to the log, as given on the command line of
+/// `compdb-cc --append [--directory <DIR>] -- <compiler> [args...]`.
+#[derive(Debug, PartialEq)]
+pub struct AppendRequest {
+    /// Working directory of the invocation; the current directory if not given.
+    pub directory: Option<PathBuf>,
+    pub compiler: OsString,
+    pub args: Vec<OsString>,
+}
+
+/// Check if append mode is requested. `--append` must be the first argument, so a
+/// compiler argument of the same name is never mistaken for it.
+pub fn has_append_flag<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.get(1).is_some_and(|a| a.as_ref() == "--append")
+}
+
+/// Parse the arguments following `--append`.
+pub fn parse_args<S: AsRef<OsStr>>(args: &[S]) -> Result<AppendRequest, String> {
+    let mut directory = None;
+    let mut args = args.iter().map(|arg| arg.as_ref());
+    loop {
+        match args.next() {
+            Some(arg) if arg == "--" => break,
+            Some(arg) if arg == "--directory" => {
+                let dir = args.next().ok_or("--directory requires a directory")?;
+                directory = Some(PathBuf::from(dir));
+            }
+            Some(arg) => {
+                return Err(format!("unknown option for --append: {}", arg.to_string_lossy()));
+            }
+            None => return Err("usage: --append [--directory <DIR>] -- <compiler> [args...]".to_string()),
+        }
+    }
+    let compiler = args.next().ok_or("--append requires a compiler after --")?.to_os_string();
+    Ok(AppendRequest {
+        directory,
+        compiler,
+        args: args.map(OsStr::to_os_string).collect(),
+    })
+}
+
+/// Append the invocation to the log at `log_file`, without running the compiler.
+pub fn run<S: AsRef<OsStr>>(log_file: &str, args: &[S]) -> Result<(), String> {
+    let request = parse_args(args)?;
+    let cwd = env::current_dir().map_err(|e| format!("failed to get current directory: {}", e))?;
+    // A relative directory is taken relative to the current one
+    let wd = match request.directory {
+        Some(dir) => cwd.join(dir),
+        None => cwd,
+    };
+    wrapper::log_command(Path::new(log_file), &wd, &request.compiler, &request.args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn detects_append_flag_only_first() {
+        assert!(has_append_flag(&["compdb-cc", "--append", "--", "gcc"]));
+        assert!(!has_append_flag(&["compdb-cc", "-c", "--append"]));
+        assert!(!has_append_flag(&["compdb-cc"]));
+    }
+
+    #[test]
+    fn parses_compiler_and_arguments() {
+        let request = parse_args(&["--", "gcc", "-c", "main.c"]).unwrap();
+        assert_eq!(
+            request,
+            AppendRequest { directory: None, compiler: "gcc".into(), args: os(&["-c", "main.c"]) }
+        );
+    }
+
+    #[test]
+    fn parses_directory() {
+        let request = parse_args(&["--directory", "/src", "--", "cc", "--", "-x"]).unwrap();
+        assert_eq!(request.directory, Some(PathBuf::from("/src")));
+        assert_eq!(request.args, os(&["--", "-x"]));
+    }
+
+    #[test]
+    fn rejects_missing_separator_or_compiler() {
+        assert!(parse_args(&["gcc", "-c", "main.c"]).unwrap_err().contains("unknown option"));
+        assert!(parse_args::<&str>(&[]).unwrap_err().contains("usage"));
+        assert!(parse_args(&["--"]).unwrap_err().contains("requires a compiler"));
+        assert!(parse_args(&["--directory"]).is_err());
+    }
+}
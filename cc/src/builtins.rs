@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+use compdb::CompileCommand;
+use crate::output;
+
+/// Language a compiler is queried for; C and C++ have different system include directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Language {
+    #[serde(rename = "c")]
+    C,
+    #[serde(rename = "c++")]
+    Cxx,
+}
+
+impl Language {
+    /// Language of an entry, from an explicit `-x`, or else the extension of its file or
+    /// the name of its compiler.
+    pub fn of(entry: &CompileCommand, compiler: &str) -> Language {
+        let args = entry.args().unwrap_or_default();
+        if let Some(language) = Language::explicit(args.get(1..).unwrap_or_default()) {
+            return language;
+        }
+        let cxx_file = Path::new(&entry.file)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext, "cc" | "cpp" | "cxx" | "c++" | "C" | "hpp"));
+        let name = Path::new(compiler).file_name().and_then(|n| n.to_str()).unwrap_or(compiler);
+        if cxx_file || name.contains("++") {
+            Language::Cxx
+        } else {
+            Language::C
+        }
+    }
+
+    /// Language selected by the last `-x` option of compiler arguments, if it is C or C++.
+    fn explicit(args: &[String]) -> Option<Language> {
+        let mut language = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("-x") {
+                Some("") => args.next().map(String::as_str),
+                Some(value) => Some(value),
+                None => continue,
+            };
+            language = match value {
+                Some("c" | "c-header" | "cpp-output") => Some(Language::C),
+                Some("c++" | "c++-header" | "c++-cpp-output") => Some(Language::Cxx),
+                Some("none") => None,
+                _ => language,
+            };
+        }
+        language
+    }
+
+    /// Name of the language for the compiler's `-x` option.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Language::C => "c",
+            Language::Cxx => "c++",
+        }
+    }
+}
+
+/// What a compiler knows without being told: its target and system include directories.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Builtins {
+    /// Target triple reported by `-dumpmachine`.
+    pub target: Option<String>,
+    /// System include directories in search order, from `-E -v`.
+    pub include_dirs: Vec<String>,
+    /// The compiler is clang, whose built-ins clangd already knows.
+    pub clang: bool,
+}
+
+/// Select the flags of compiler arguments, without the compiler, that change its target
+/// or system include directories: the sysroot, the target, `-m32`/`-m64` and `-stdlib=`.
+pub fn target_flags(args: &[String]) -> Vec<String> {
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sysroot" | "-isysroot" | "-target" => {
+                flags.push(arg.clone());
+                flags.extend(args.next().cloned());
+            }
+            "-m32" | "-m64" => flags.push(arg.clone()),
+            _ if ["--sysroot=", "-isysroot", "--target=", "-stdlib="].iter().any(|p| arg.starts_with(p)) => {
+                flags.push(arg.clone())
+            }
+            _ => {}
+        }
+    }
+    flags
+}
+
+/// Extract the `#include <...>` search list from the output of `-E -v`.
+/// Framework directories are skipped, since they cannot be passed with `-isystem`.
+pub fn parse_include_dirs(verbose_output: &str) -> Vec<String> {
+    verbose_output
+        .lines()
+        .skip_while(|line| !line.starts_with("#include <...> search starts here:"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("End of search list."))
+        .map(str::trim)
+        .filter(|dir| !dir.is_empty() && !dir.ends_with("(framework directory)"))
+        .map(|dir| compdb::paths::normalize(Path::new(dir)).to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Check if the output of `-dM -E` defines `__clang__`.
+pub fn defines_clang(defines: &str) -> bool {
+    defines.lines().any(|line| line.starts_with("#define __clang__ "))
+}
+
+/// Run the compiler with `args` and return its stdout and stderr.
+/// Messages are forced to English, since the include search list is parsed.
+fn run_compiler(compiler: &Path, wd: &Path, args: &[&str]) -> Result<(String, String), String> {
+    let mut command = Command::new(compiler);
+    command.args(args).env("LC_ALL", "C").stdin(Stdio::null());
+    if wd.is_dir() {
+        command.current_dir(wd);
+    }
+    let output = command
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", compiler.display(), e))?;
+    if !output.status.success() {
+        return Err(format!("{} {} failed with {}", compiler.display(), args.join(" "), output.status));
+    }
+    Ok((
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+/// Query the built-ins of a compiler for a language, with the target flags of an entry.
+pub fn query(compiler: &Path, wd: &Path, language: Language, flags: &[String]) -> Result<Builtins, String> {
+    let lang = language.as_str();
+    let with_flags = |args: &[&'static str]| -> Vec<&str> {
+        flags.iter().map(String::as_str).chain(args.iter().copied()).collect()
+    };
+    let (_, verbose) = run_compiler(compiler, wd, &with_flags(&["-E", "-v", "-x", lang, "/dev/null"]))?;
+    let (machine, _) = run_compiler(compiler, wd, &with_flags(&["-dumpmachine"]))?;
+    let (defines, _) = run_compiler(compiler, wd, &with_flags(&["-dM", "-E", "-x", lang, "/dev/null"]))?;
+    let target = machine.trim();
+    Ok(Builtins {
+        target: (!target.is_empty()).then(|| target.to_string()),
+        include_dirs: parse_include_dirs(&verbose),
+        clang: defines_clang(&defines),
+    })
+}
+
+/// Find the executable a compiler name refers to: relative to `wd` if it contains a
+/// slash, otherwise on `PATH`.
+pub fn resolve_compiler(compiler: &str, wd: &Path) -> Option<PathBuf> {
+    if compiler.contains('/') {
+        let path = wd.join(compiler);
+        return path.is_file().then_some(path);
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(compiler))
+        .find(|path| path.is_file())
+}
+
+/// Inject the built-ins of a non-clang compiler into an entry: `--target`, `-nostdinc`
+/// and an `-isystem` for every system include directory, right after the compiler.
+/// A target or `-nostdinc` given by the entry itself is respected. The built-ins must be
+/// queried with the entry's target flags, so the directories are those of its sysroot.
+pub fn apply(entry: &mut CompileCommand, builtins: &Builtins) {
+    if builtins.clang {
+        return;
+    }
+    let Ok(mut args) = entry.args() else {
+        return;
+    };
+    let mut injected = Vec::new();
+    if let Some(target) = &builtins.target {
+        let has_target = args.iter().any(|arg| arg == "-target" || arg.starts_with("--target="));
+        if !has_target {
+            injected.push(format!("--target={}", target));
+        }
+    }
+    if !builtins.include_dirs.is_empty() && !args.iter().any(|arg| arg == "-nostdinc") {
+        injected.push("-nostdinc".to_string());
+        for dir in &builtins.include_dirs {
+            injected.push("-isystem".to_string());
+            injected.push(dir.clone());
+        }
+    }
+    if injected.is_empty() || args.is_empty() {
+        return;
+    }
+    args.splice(1..1, injected);
+    entry.set_args(args);
+}
+
+/// A cached query result, valid while the compiler binary is unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheRecord {
+    compiler: PathBuf,
+    language: Language,
+    #[serde(default)]
+    flags: Vec<String>,
+    size: u64,
+    mtime: u64,
+    builtins: Builtins,
+}
+
+/// Size and modification time identifying a version of a compiler binary.
+fn fingerprint(compiler: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(compiler).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some((metadata.len(), mtime))
+}
+
+/// Queries each distinct compiler once per run and keeps the results in a cache file,
+/// so later runs only query compilers that were added or changed.
+pub struct BuiltinsCache {
+    file: Option<PathBuf>,
+    records: Vec<CacheRecord>,
+    queried: HashMap<(String, Language, Vec<String>), Result<Builtins, String>>,
+    changed: bool,
+}
+
+impl BuiltinsCache {
+    /// Load the cache from `file`; a missing or unreadable cache file starts empty.
+    pub fn load(file: Option<PathBuf>) -> Self {
+        let records = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        BuiltinsCache { file, records, queried: HashMap::new(), changed: false }
+    }
+
+    /// Built-ins of the compiler of an entry with its target flags, querying it if it isn't cached.
+    pub fn get(&mut self, compiler: &str, wd: &str, language: Language, flags: &[String]) -> Result<Builtins, String> {
+        // A relative compiler path names a different compiler in every directory
+        let name = if compiler.contains('/') {
+            Path::new(wd).join(compiler).to_string_lossy().into_owned()
+        } else {
+            compiler.to_string()
+        };
+        let key = (name, language, flags.to_vec());
+        if let Some(result) = self.queried.get(&key) {
+            return result.clone();
+        }
+        let result = self.lookup_or_query(compiler, Path::new(wd), language, flags);
+        self.queried.insert(key, result.clone());
+        result
+    }
+
+    fn lookup_or_query(
+        &mut self,
+        compiler: &str,
+        wd: &Path,
+        language: Language,
+        flags: &[String],
+    ) -> Result<Builtins, String> {
+        let path = resolve_compiler(compiler, wd).ok_or_else(|| format!("compiler {} not found", compiler))?;
+        let (size, mtime) = fingerprint(&path).ok_or_else(|| format!("cannot stat compiler {}", path.display()))?;
+        let same = |r: &CacheRecord| r.compiler == path && r.language == language && r.flags == flags;
+        if let Some(record) = self.records.iter().find(|r| same(r) && r.size == size && r.mtime == mtime) {
+            return Ok(record.builtins.clone());
+        }
+        let builtins = query(&path, wd, language, flags)?;
+        self.records.retain(|r| !same(r));
+        let flags = flags.to_vec();
+        self.records.push(CacheRecord { compiler: path, language, flags, size, mtime, builtins: builtins.clone() });
+        self.changed = true;
+        Ok(builtins)
+    }
+
+    /// Write the cache file if new compilers were queried.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.file {
+            Some(file) if self.changed => output::write_atomic(file, |out| {
+                serde_json::to_writer_pretty(&mut *out, &self.records)?;
+                Ok(())
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Default location of the cache file for a log: next to it, e.g. `build.log.builtins.json`.
+pub fn cache_path(log_path: &Path) -> PathBuf {
+    let log = log_path.as_os_str().to_string_lossy();
+    PathBuf::from(format!("{}.builtins.json", log.trim_end_matches('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    const GCC_VERBOSE: &str = "\
+Using built-in specs.
+#include \"...\" search starts here:
+#include <...> search starts here:
+ /usr/lib/gcc-cross/aarch64-linux-gnu/12/include
+ /usr/aarch64-linux-gnu/include
+ /Library/Frameworks (framework directory)
+End of search list.
+";
+
+    /// Write a fake GCC that logs each invocation to `calls`.
+    fn fake_gcc(dir: &Path) -> PathBuf {
+        let path = dir.join("aarch64-linux-gnu-gcc");
+        let script = format!(
+            "#!/bin/sh\necho \"$@\" >> {calls}\ncase \" $* \" in\n  *\" -dumpmachine \"*) echo aarch64-linux-gnu ;;\n  *\" -dM \"*) echo '#define __GNUC__ 12' ;;\n  *) printf '{verbose}' >&2 ;;\nesac\n",
+            calls = dir.join("calls").display(),
+            verbose = GCC_VERBOSE,
+        );
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn calls(dir: &Path) -> usize {
+        fs::read_to_string(dir.join("calls")).map(|s| s.lines().count()).unwrap_or(0)
+    }
+
+    fn builtins() -> Builtins {
+        Builtins {
+            target: Some("aarch64-linux-gnu".to_string()),
+            include_dirs: vec!["/usr/aarch64-linux-gnu/include".to_string()],
+            clang: false,
+        }
+    }
+
+    #[test]
+    fn parses_include_search_list() {
+        assert_eq!(
+            parse_include_dirs(GCC_VERBOSE),
+            vec!["/usr/lib/gcc-cross/aarch64-linux-gnu/12/include", "/usr/aarch64-linux-gnu/include"]
+        );
+        assert!(parse_include_dirs("no search list").is_empty());
+    }
+
+    #[test]
+    fn detects_clang() {
+        assert!(defines_clang("#define __GNUC__ 4\n#define __clang__ 1\n"));
+        assert!(!defines_clang("#define __GNUC__ 12\n#define __clang_version__x\n"));
+    }
+
+    #[test]
+    fn detects_language() {
+        let entry = CompileCommand::with_arguments("/b", "/b/a.cpp", vec![]);
+        assert_eq!(Language::of(&entry, "gcc"), Language::Cxx);
+        let entry = CompileCommand::with_arguments("/b", "/b/a.c", vec![]);
+        assert_eq!(Language::of(&entry, "gcc"), Language::C);
+        assert_eq!(Language::of(&entry, "/usr/bin/aarch64-linux-gnu-g++"), Language::Cxx);
+        let entry = CompileCommand::with_command("/b", "a.inc", "gcc -x c++ -c a.inc");
+        assert_eq!(Language::of(&entry, "gcc"), Language::Cxx);
+        let entry = CompileCommand::with_command("/b", "a.cpp", "g++ -xc -c a.cpp");
+        assert_eq!(Language::of(&entry, "g++"), Language::C);
+        let entry = CompileCommand::with_command("/b", "a.cpp", "gcc -x c -x none -c a.cpp");
+        assert_eq!(Language::of(&entry, "gcc"), Language::Cxx);
+    }
+
+    #[test]
+    fn selects_target_flags() {
+        let args: Vec<String> = [
+            "-O2", "--sysroot", "/sr", "-isysroot/sdk", "-target", "arm-none-eabi", "--target=x", "-m32",
+            "-stdlib=libc++", "-I", "inc", "-c", "a.c",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(
+            target_flags(&args),
+            ["--sysroot", "/sr", "-isysroot/sdk", "-target", "arm-none-eabi", "--target=x", "-m32", "-stdlib=libc++"]
+        );
+    }
+
+    #[test]
+    fn injects_target_and_include_dirs_after_compiler() {
+        let mut entry = CompileCommand::with_command("/b", "a.c", "gcc -c a.c");
+        apply(&mut entry, &builtins());
+        assert_eq!(
+            entry.command.as_deref(),
+            Some("gcc --target=aarch64-linux-gnu -nostdinc -isystem /usr/aarch64-linux-gnu/include -c a.c")
+        );
+    }
+
+    #[test]
+    fn respects_explicit_target_and_nostdinc() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut entry = CompileCommand::with_arguments("/b", "a.c", args(&["gcc", "--target=x", "-nostdinc", "a.c"]));
+        apply(&mut entry, &builtins());
+        assert_eq!(entry.arguments, Some(args(&["gcc", "--target=x", "-nostdinc", "a.c"])));
+
+        let mut entry = CompileCommand::with_arguments("/b", "a.c", args(&["clang", "a.c"]));
+        apply(&mut entry, &Builtins { clang: true, ..builtins() });
+        assert_eq!(entry.arguments, Some(args(&["clang", "a.c"])));
+    }
+
+    #[test]
+    fn queries_each_compiler_once_and_caches_results() {
+        let temp_dir = TempDir::new().unwrap();
+        let compiler = fake_gcc(temp_dir.path());
+        let compiler = compiler.to_str().unwrap();
+        let wd = temp_dir.path().to_str().unwrap();
+        let cache_file = temp_dir.path().join("builtins.json");
+
+        let mut cache = BuiltinsCache::load(Some(cache_file.clone()));
+        let first = cache.get(compiler, wd, Language::C, &[]).unwrap();
+        cache.get(compiler, wd, Language::C, &[]).unwrap();
+        assert_eq!(first, Builtins {
+            target: Some("aarch64-linux-gnu".to_string()),
+            include_dirs: parse_include_dirs(GCC_VERBOSE),
+            clang: false,
+        });
+        assert_eq!(calls(temp_dir.path()), 3);
+        cache.save().unwrap();
+
+        let mut cache = BuiltinsCache::load(Some(cache_file));
+        assert_eq!(cache.get(compiler, wd, Language::C, &[]).unwrap(), first);
+        assert_eq!(calls(temp_dir.path()), 3);
+        cache.get(compiler, wd, Language::Cxx, &[]).unwrap();
+        assert_eq!(calls(temp_dir.path()), 6);
+    }
+
+    #[test]
+    fn queries_with_target_flags() {
+        let temp_dir = TempDir::new().unwrap();
+        let compiler = fake_gcc(temp_dir.path());
+        let compiler = compiler.to_str().unwrap();
+        let wd = temp_dir.path().to_str().unwrap();
+        let sysroot = vec!["--sysroot=/opt/sysroot".to_string()];
+
+        let mut cache = BuiltinsCache::load(None);
+        cache.get(compiler, wd, Language::C, &[]).unwrap();
+        cache.get(compiler, wd, Language::C, &sysroot).unwrap();
+        cache.get(compiler, wd, Language::C, &sysroot).unwrap();
+
+        let calls = fs::read_to_string(temp_dir.path().join("calls")).unwrap();
+        let with_sysroot: Vec<&str> = calls.lines().filter_map(|line| line.strip_prefix("--sysroot=/opt/sysroot ")).collect();
+        assert_eq!(calls.lines().count(), 6);
+        assert_eq!(with_sysroot, ["-E -v -x c /dev/null", "-dumpmachine", "-dM -E -x c /dev/null"]);
+    }
+
+    #[test]
+    fn reports_missing_compiler() {
+        let mut cache = BuiltinsCache::load(None);
+        let err = cache.get("./no-such-compiler", "/", Language::C, &[]).unwrap_err();
+        assert!(err.contains("not found"));
+    }
+
+    #[test]
+    fn cache_file_is_next_to_log() {
+        assert_eq!(cache_path(Path::new("/tmp/build.log")), Path::new("/tmp/build.log.builtins.json"));
+        assert_eq!(cache_path(Path::new("/tmp/logs/")), Path::new("/tmp/logs.builtins.json"));
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::json;
+use crate::generate::{GenerateStats, SkipReason};
+use crate::output;
+
+/// How much generation prints about skipped and suspicious log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    /// Print nothing but errors.
+    Quiet,
+    /// Print problems with the log itself and a summary.
+    #[default]
+    Normal,
+    /// Print every diagnostic together with the offending log line.
+    Verbose,
+}
+
+/// Kind of diagnostic reported while generating a compilation database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    /// The line is not valid UTF-8, not valid JSON or not a valid log record.
+    InvalidEntry,
+    /// The line was written by a newer wrapper with an unknown schema version.
+    UnsupportedVersion,
+    /// The record contains values that are not valid UTF-8.
+    NotUtf8,
+    /// A compile invocation without a recognised source file.
+    NoSource,
+    /// An invocation that doesn't compile anything, such as a link step.
+    NotCompile,
+    /// The working directory of an entry no longer exists.
+    MissingDirectory,
+    /// An entry identical to one written before.
+    Duplicate,
+    /// The built-ins of an entry's compiler could not be queried.
+    CompilerQuery,
+}
+
+impl Category {
+    /// All categories, in the order they are summarised.
+    pub const ALL: [Category; 8] = [
+        Category::InvalidEntry,
+        Category::UnsupportedVersion,
+        Category::NotUtf8,
+        Category::NoSource,
+        Category::NotCompile,
+        Category::MissingDirectory,
+        Category::Duplicate,
+        Category::CompilerQuery,
+    ];
+
+    /// Check if the category marks a malformed log line, which fails strict mode.
+    pub fn is_malformed(self) -> bool {
+        matches!(self, Category::InvalidEntry | Category::UnsupportedVersion)
+    }
+
+    /// Check if every occurrence is printed by default instead of only being counted.
+    /// Link steps and duplicates are routine in any build and would flood the terminal.
+    fn is_shown_by_default(self) -> bool {
+        matches!(self, Category::InvalidEntry | Category::UnsupportedVersion | Category::NotUtf8)
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Category::InvalidEntry => "invalid log lines",
+            Category::UnsupportedVersion => "lines with an unsupported schema version",
+            Category::NotUtf8 => "non-UTF-8 entries",
+            Category::NoSource => "compile commands without a source file",
+            Category::NotCompile => "non-compile invocations",
+            Category::MissingDirectory => "entries with a missing directory",
+            Category::Duplicate => "duplicate entries",
+            Category::CompilerQuery => "entries whose compiler could not be queried",
+        }
+    }
+}
+
+impl From<&SkipReason> for Category {
+    fn from(reason: &SkipReason) -> Self {
+        match reason {
+            SkipReason::InvalidLine | SkipReason::InvalidEntry => Category::InvalidEntry,
+            SkipReason::UnsupportedVersion(_) => Category::UnsupportedVersion,
+            SkipReason::NotUtf8 => Category::NotUtf8,
+            SkipReason::NoSource => Category::NoSource,
+            SkipReason::NotCompile => Category::NotCompile,
+        }
+    }
+}
+
+/// A diagnostic for one log line.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// 1-based number of the line in the log, counting across all shards.
+    pub line: usize,
+    pub category: Category,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Collects the diagnostics of one generation run.
+/// Diagnostics are printed as they occur according to the verbosity and counted per
+/// category. They are only kept in memory if a report is requested.
+pub struct Diagnostics {
+    verbosity: Verbosity,
+    counts: [usize; Category::ALL.len()],
+    records: Option<Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn new(verbosity: Verbosity, keep_records: bool) -> Self {
+        Diagnostics {
+            verbosity,
+            counts: [0; Category::ALL.len()],
+            records: keep_records.then(Vec::new),
+        }
+    }
+
+    /// Record a diagnostic for the log line `text`.
+    pub fn add(&mut self, diagnostic: Diagnostic, text: &str) {
+        match self.verbosity {
+            Verbosity::Verbose => eprintln!("warning: {}: {}", diagnostic, text),
+            Verbosity::Normal if diagnostic.category.is_shown_by_default() => {
+                eprintln!("warning: {}", diagnostic)
+            }
+            _ => {}
+        }
+        self.counts[diagnostic.category as usize] += 1;
+        if let Some(records) = &mut self.records {
+            records.push(diagnostic);
+        }
+    }
+
+    /// Number of diagnostics of a category.
+    pub fn count(&self, category: Category) -> usize {
+        self.counts[category as usize]
+    }
+
+    /// Number of malformed log lines.
+    pub fn malformed(&self) -> usize {
+        Category::ALL.iter().filter(|c| c.is_malformed()).map(|&c| self.count(c)).sum()
+    }
+
+    /// Print a summary of the run, unless running quietly.
+    pub fn print_summary(&self, stats: &GenerateStats) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        eprintln!("Generated {} entries from {} log lines", stats.entries, stats.lines());
+        for category in Category::ALL {
+            let count = self.count(category);
+            if count > 0 {
+                eprintln!("  {} {}", count, category.label());
+            }
+        }
+    }
+
+    /// Write a machine-readable JSON report of the run to `path`.
+    pub fn write_report(&self, path: &Path, stats: &GenerateStats) -> Result<(), Box<dyn std::error::Error>> {
+        let counts: serde_json::Map<String, serde_json::Value> = Category::ALL
+            .iter()
+            .map(|&c| (serde_json::to_value(c).unwrap().as_str().unwrap().to_string(), json!(self.count(c))))
+            .collect();
+        let report = json!({
+            "lines": stats.lines(),
+            "entries": stats.entries,
+            "skipped": stats.skipped,
+            "duplicates": stats.duplicates,
+            "counts": counts,
+            "diagnostics": self.records.as_deref().unwrap_or_default(),
+        });
+        output::write_atomic(path, |out| {
+            serde_json::to_writer_pretty(&mut *out, &report)?;
+            Ok(writeln!(out)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn diagnostic(line: usize, category: Category) -> Diagnostic {
+        Diagnostic { line, category, message: "message".to_string() }
+    }
+
+    #[test]
+    fn counts_per_category() {
+        let mut diagnostics = Diagnostics::new(Verbosity::Quiet, false);
+        diagnostics.add(diagnostic(1, Category::NotCompile), "");
+        diagnostics.add(diagnostic(2, Category::NotCompile), "");
+        diagnostics.add(diagnostic(3, Category::Duplicate), "");
+
+        assert_eq!(diagnostics.count(Category::NotCompile), 2);
+        assert_eq!(diagnostics.count(Category::Duplicate), 1);
+        assert_eq!(diagnostics.count(Category::InvalidEntry), 0);
+        assert_eq!(diagnostics.malformed(), 0);
+    }
+
+    #[test]
+    fn counts_malformed_lines() {
+        let mut diagnostics = Diagnostics::new(Verbosity::Quiet, false);
+        diagnostics.add(diagnostic(1, Category::InvalidEntry), "");
+        diagnostics.add(diagnostic(2, Category::UnsupportedVersion), "");
+        diagnostics.add(diagnostic(3, Category::NotUtf8), "");
+
+        assert_eq!(diagnostics.malformed(), 2);
+    }
+
+    #[test]
+    fn formats_with_line_number() {
+        let d = Diagnostic { line: 42, category: Category::InvalidEntry, message: "invalid entry".to_string() };
+        assert_eq!(d.to_string(), "line 42: invalid entry");
+    }
+
+    #[test]
+    fn writes_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("report.json");
+        let mut diagnostics = Diagnostics::new(Verbosity::Quiet, true);
+        diagnostics.add(diagnostic(2, Category::InvalidEntry), "garbage");
+        let stats = GenerateStats { entries: 1, skipped: 1, duplicates: 0 };
+
+        diagnostics.write_report(&path, &stats).unwrap();
+
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report["lines"], 2);
+        assert_eq!(report["entries"], 1);
+        assert_eq!(report["counts"]["invalid_entry"], 1);
+        assert_eq!(report["counts"]["duplicate"], 0);
+        assert_eq!(report["diagnostics"], json!([{"line": 2, "category": "invalid_entry", "message": "message"}]));
+    }
+}
@@ -1,43 +1,96 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
-use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use compdb::database::Writer;
+use compdb::compat::{self, Compat};
+use compdb::paths::PathRewriter;
+use compdb::CompileCommand;
+use crate::builtins::{self, BuiltinsCache, Language};
+use crate::diagnostics::{Category, Diagnostic, Diagnostics, Verbosity};
+use crate::schema::{self, SchemaError};
+use crate::{log, output, rsp};
+
+/// Reason a log line did not produce a compilation database entry.
+#[derive(Debug, PartialEq)]
+pub enum SkipReason {
+    /// The line itself is not valid UTF-8.
+    InvalidLine,
+    /// The line is not valid JSON or has no argument list.
+    InvalidEntry,
+    /// The line was written by a newer wrapper with an unknown schema version.
+    UnsupportedVersion(u64),
+    /// A compile command without a recognised source file.
+    NoSource,
+    /// The invocation doesn't compile anything, e.g. a link step or a version query.
+    NotCompile,
+    /// The directory, compiler or an argument is not valid UTF-8 and cannot be written as JSON.
+    NotUtf8,
+}
 
-/// Default compiler used when log entry doesn't specify one (for backwards compatibility).
-const DEFAULT_COMPILER: &str = "/usr/bin/gcc";
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::InvalidLine => write!(f, "invalid line: not valid UTF-8"),
+            SkipReason::InvalidEntry => write!(f, "invalid entry"),
+            SkipReason::UnsupportedVersion(v) => write!(f, "unsupported log schema version {}", v),
+            SkipReason::NoSource => write!(f, "no source file"),
+            SkipReason::NotCompile => write!(f, "not a compile command"),
+            SkipReason::NotUtf8 => write!(f, "non-UTF-8 entry cannot be represented in JSON"),
+        }
+    }
+}
 
 /// Parse a single log entry and return a compilation database entry if valid.
 /// Returns None if the entry has no source files or invalid format.
-pub fn parse_log_entry(line: &str, wd_override: Option<&str>) -> Option<Value> {
-    let it: Value = serde_json::from_str(line).ok()?;
-
-    let wd = wd_override.unwrap_or_else(|| it["wd"].as_str().unwrap_or(""));
-    let compiler = it["compiler"].as_str().unwrap_or(DEFAULT_COMPILER);
-    let args_value = &it["args"];
+pub fn parse_log_entry(line: &str, wd_override: Option<&str>) -> Option<CompileCommand> {
+    parse_log_line(line, wd_override).ok()
+}
 
-    if !args_value.is_array() {
-        return None;
+/// Parse a single log entry, reporting why it was skipped if it is not usable.
+pub fn parse_log_line(line: &str, wd_override: Option<&str>) -> Result<CompileCommand, SkipReason> {
+    let entry = schema::parse_entry(line).map_err(|e| match e {
+        SchemaError::Invalid => SkipReason::InvalidEntry,
+        SchemaError::UnsupportedVersion(v) => SkipReason::UnsupportedVersion(v),
+    })?;
+
+    let wd = match wd_override {
+        Some(wd) => OsString::from(wd),
+        None => entry.wd.0,
+    };
+
+    let mut args = vec![entry.compiler.0];
+    args.extend(entry.args.into_iter().map(|arg| arg.0));
+
+    // Expand response files the wrapper could not read that still exist on disk
+    if rsp::has_response_files(&args[1..]) {
+        let expanded = rsp::expand_response_files(&args[1..], Path::new(&wd));
+        args.truncate(1);
+        args.extend(expanded);
     }
 
-    let mut args = vec![compiler.to_string()];
-    for arg in args_value.as_array().unwrap() {
-        if let Some(arg_str) = arg.as_str() {
-            args.push(arg_str.to_string());
-        }
-    }
+    // JSON output can only carry UTF-8, so entries with raw bytes are reported instead
+    let wd = wd.into_string().map_err(|_| SkipReason::NotUtf8)?;
+    let args = args
+        .into_iter()
+        .map(OsString::into_string)
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|_| SkipReason::NotUtf8)?;
 
     // Find source files in arguments
-    let srcs = find_source_files(&args, wd);
+    let srcs = find_source_files(&args, &wd);
 
-    if srcs.is_empty() {
-        return None;
-    }
+    let Some(file) = srcs.last() else {
+        let compiles = args[1..].iter().any(|arg| arg == "-c" || arg == "-S");
+        return Err(if compiles { SkipReason::NoSource } else { SkipReason::NotCompile });
+    };
 
-    Some(json!({
-        "directory": wd,
-        "arguments": args,
-        "file": srcs.last().unwrap(),
-    }))
+    let file = file.clone();
+    Ok(CompileCommand::with_arguments(wd, file, args))
 }
 
 /// Find source files in the arguments list, returning their full paths.
@@ -48,40 +101,306 @@ pub fn find_source_files(args: &[String], wd: &str) -> Vec<String> {
         .collect()
 }
 
-/// Generate a compilation database from a log file.
+/// Number of log lines parsed together in one batch.
+/// Only one batch is held in memory at a time.
+const BATCH_SIZE: usize = 4096;
+
+/// Options controlling database generation.
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Number of threads used to parse log lines; 1 parses on the calling thread.
+    pub jobs: usize,
+    /// How much to print about skipped and suspicious log lines.
+    pub verbosity: Verbosity,
+    /// Fail without touching the database if any log line is malformed.
+    pub strict: bool,
+    /// File to write a JSON report of all diagnostics to.
+    pub report: Option<PathBuf>,
+    /// Normalization and prefix mappings applied to the paths of every entry.
+    pub paths: PathRewriter,
+    /// Query the target and system include directories of non-clang compilers and add
+    /// them to their entries, so clangd needs no `--query-driver`.
+    pub query_builtins: bool,
+    /// Flag rewriting applied to every entry, e.g. to make GCC flags acceptable to clang.
+    pub compat: Option<Compat>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            jobs: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            verbosity: Verbosity::default(),
+            strict: false,
+            report: None,
+            paths: PathRewriter { maps: Vec::new(), canonicalize: true },
+            query_builtins: false,
+            compat: None,
+        }
+    }
+}
+
+/// Parse the command line options of generate mode, excluding the program name.
+pub fn parse_args<S: AsRef<OsStr>>(args: &[S]) -> Result<GenerateOptions, String> {
+    let mut options = GenerateOptions::default();
+    let mut gcc_compat = false;
+    let mut rules = Vec::new();
+    let mut args = args.iter().map(|arg| arg.as_ref());
+    while let Some(arg) = args.next() {
+        let arg = arg.to_str().ok_or_else(|| format!("invalid option: {}", arg.to_string_lossy()))?;
+        match arg {
+            "--generate" => {}
+            "--quiet" => options.verbosity = Verbosity::Quiet,
+            "--verbose" => options.verbosity = Verbosity::Verbose,
+            "--strict" => options.strict = true,
+            "--no-canonicalize" => options.paths.canonicalize = false,
+            "--query-builtins" => options.query_builtins = true,
+            "--gcc-compat" => gcc_compat = true,
+            "--compat-rule" => {
+                let rule = args.next().ok_or("--compat-rule requires a rule")?;
+                rules.push(rule.to_string_lossy().parse()?);
+            }
+            "--compat-rules" => {
+                let path = args.next().ok_or("--compat-rules requires a file name")?;
+                rules.extend(compat::load_rules(Path::new(path))?);
+            }
+            "--report" => {
+                let path = args.next().ok_or("--report requires a file name")?;
+                options.report = Some(PathBuf::from(path));
+            }
+            "--map" => {
+                let map = args.next().ok_or("--map requires FROM=TO")?;
+                options.paths.maps.push(map.to_string_lossy().parse()?);
+            }
+            _ => {
+                if let Some(path) = arg.strip_prefix("--report=") {
+                    options.report = Some(PathBuf::from(path));
+                } else if let Some(map) = arg.strip_prefix("--map=") {
+                    options.paths.maps.push(map.parse()?);
+                } else if let Some(rule) = arg.strip_prefix("--compat-rule=") {
+                    rules.push(rule.parse()?);
+                } else if let Some(path) = arg.strip_prefix("--compat-rules=") {
+                    rules.extend(compat::load_rules(Path::new(path))?);
+                } else {
+                    return Err(format!("unknown option for --generate: {}", arg));
+                }
+            }
+        }
+    }
+    // Own rules extend the GCC table, so they imply it
+    if gcc_compat || !rules.is_empty() {
+        options.compat = Some(Compat::gcc_with(rules));
+    }
+    Ok(options)
+}
+
+/// Summary of a generated compilation database.
+#[derive(Debug, Default, PartialEq)]
+pub struct GenerateStats {
+    /// Entries written to the database.
+    pub entries: usize,
+    /// Log lines that did not produce an entry.
+    pub skipped: usize,
+    /// Entries left out because an identical entry was already written.
+    pub duplicates: usize,
+}
+
+impl GenerateStats {
+    /// Number of log lines read.
+    pub fn lines(&self) -> usize {
+        self.entries + self.skipped + self.duplicates
+    }
+}
+
+/// The entries written so far, for deduplication by directory, file and arguments.
+/// Only a 128-bit digest made of two independently seeded 64-bit hashes is kept per
+/// entry, so memory stays small for huge databases while distinct entries practically
+/// never collide.
+struct SeenEntries {
+    hashers: (RandomState, RandomState),
+    digests: HashSet<u128>,
+}
+
+impl SeenEntries {
+    fn new() -> Self {
+        SeenEntries { hashers: (RandomState::new(), RandomState::new()), digests: HashSet::new() }
+    }
+
+    /// Record an entry, returning false if an identical one was recorded before.
+    fn insert(&mut self, entry: &CompileCommand) -> bool {
+        let key = (&entry.directory, &entry.file, &entry.arguments);
+        let digest = (u128::from(self.hashers.0.hash_one(key)) << 64) | u128::from(self.hashers.1.hash_one(key));
+        self.digests.insert(digest)
+    }
+}
+
+/// Parse a log line, normalize and remap the paths of the resulting entry and rewrite
+/// its flags if requested.
+fn parse_and_rewrite(line: &[u8], options: &GenerateOptions) -> Result<CompileCommand, SkipReason> {
+    let line = std::str::from_utf8(line).map_err(|_| SkipReason::InvalidLine)?;
+    let mut entry = parse_log_line(line, None)?;
+    // Generated entries use the arguments form, which never fails to split
+    let _ = options.paths.rewrite(&mut entry);
+    if let Some(compat) = &options.compat {
+        let _ = compat.rewrite(&mut entry);
+    }
+    Ok(entry)
+}
+
+/// Parse a batch of log lines, in parallel if requested, preserving their order.
+fn parse_batch(lines: &[Vec<u8>], options: &GenerateOptions) -> Vec<Result<CompileCommand, SkipReason>> {
+    let jobs = options.jobs;
+    if jobs <= 1 || lines.len() < 2 * jobs {
+        return lines.iter().map(|line| parse_and_rewrite(line, options)).collect();
+    }
+    let chunk_size = lines.len().div_ceil(jobs);
+    thread::scope(|scope| {
+        let handles: Vec<_> = lines
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk.iter().map(|line| parse_and_rewrite(line, options)).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("log parser thread panicked"))
+            .collect()
+    })
+}
+
+/// Generate a compilation database from a log file or shard directory.
 /// Writes output to the specified destination file.
-pub fn generate_db(log_file: &str, dst: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
-    let file = File::open(log_file)?;
-    let reader = BufReader::new(file);
+pub fn generate_db(log_file: &str, dst: &str) -> Result<GenerateStats, Box<dyn std::error::Error>> {
+    generate_db_with(log_file, dst, &GenerateOptions::default())
+}
 
-    let mut db = Vec::new();
+/// Generate a compilation database, streaming entries to `dst` as the log is read.
+/// `dst` is replaced atomically under a lock on `<dst>.lock`. Memory use is bounded
+/// by one batch of log lines plus a 16-byte digest per written entry.
+/// In strict mode a malformed log line fails generation and leaves `dst` unchanged.
+pub fn generate_db_with(
+    log_file: &str,
+    dst: &str,
+    options: &GenerateOptions,
+) -> Result<GenerateStats, Box<dyn std::error::Error>> {
+    let lines = log::read_lines(Path::new(log_file))?;
+    let dst = Path::new(dst);
+    let _lock = output::lock(dst)?;
+
+    let mut stats = GenerateStats::default();
+    let mut diagnostics = Diagnostics::new(options.verbosity, options.report.is_some());
+    let mut builtins = options
+        .query_builtins
+        .then(|| BuiltinsCache::load(Some(builtins::cache_path(Path::new(log_file)))));
+    let written = output::write_atomic(dst, |out| {
+        write_entries(lines, out, options, builtins.as_mut(), &mut stats, &mut diagnostics)?;
+        match diagnostics.malformed() {
+            n if options.strict && n > 0 => {
+                Err(format!("{} malformed log lines, {} left unchanged", n, dst.display()).into())
+            }
+            _ => Ok(()),
+        }
+    });
 
-    for line in reader.lines() {
-        let line = line?;
-        if let Some(entry) = parse_log_entry(&line, None) {
-            db.push(entry);
-        } else {
-            eprintln!("warning no src {}", line);
+    // The cache only saves time, so failing to write it doesn't fail generation
+    if let Err(e) = builtins.as_ref().map_or(Ok(()), BuiltinsCache::save) {
+        if options.verbosity != Verbosity::Quiet {
+            eprintln!("warning: failed to write compiler built-ins cache: {}", e);
         }
     }
+    diagnostics.print_summary(&stats);
+    if let Some(report) = &options.report {
+        diagnostics.write_report(report, &stats)?;
+    }
+    written?;
+    Ok(stats)
+}
 
-    let mut output = File::create(dst)?;
-    output.write_all(serde_json::to_string_pretty(&db)?.as_bytes())?;
+/// Parse log lines in batches and write the resulting entries to `out`.
+fn write_entries(
+    lines: impl Iterator<Item = io::Result<Vec<u8>>>,
+    out: impl Write,
+    options: &GenerateOptions,
+    mut builtins: Option<&mut BuiltinsCache>,
+    stats: &mut GenerateStats,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = Writer::new(out);
+    let mut seen = SeenEntries::new();
+    let mut dir_exists: HashMap<String, bool> = HashMap::new();
+    let mut line_no = 0;
+
+    let mut flush_batch = |batch: &mut Vec<Vec<u8>>| -> Result<(), Box<dyn std::error::Error>> {
+        for (line, parsed) in batch.iter().zip(parse_batch(batch, options)) {
+            line_no += 1;
+            let line = &String::from_utf8_lossy(line);
+            let mut entry = match parsed {
+                Ok(entry) => entry,
+                Err(reason) => {
+                    let category = Category::from(&reason);
+                    diagnostics.add(Diagnostic { line: line_no, category, message: reason.to_string() }, line);
+                    stats.skipped += 1;
+                    continue;
+                }
+            };
+            if let Some(cache) = builtins.as_deref_mut() {
+                let args = entry.arguments.clone().unwrap_or_default();
+                let compiler = args.first().cloned().unwrap_or_default();
+                let language = Language::of(&entry, &compiler);
+                let flags = builtins::target_flags(args.get(1..).unwrap_or_default());
+                match cache.get(&compiler, &entry.directory, language, &flags) {
+                    Ok(found) => builtins::apply(&mut entry, &found),
+                    Err(message) => {
+                        let category = Category::CompilerQuery;
+                        diagnostics.add(Diagnostic { line: line_no, category, message }, line);
+                    }
+                }
+            }
+            if !seen.insert(&entry) {
+                let message = format!("duplicate entry for {}", entry.file);
+                diagnostics.add(Diagnostic { line: line_no, category: Category::Duplicate, message }, line);
+                stats.duplicates += 1;
+                continue;
+            }
+            let dir = &entry.directory;
+            let exists = *dir_exists.entry(dir.clone()).or_insert_with(|| Path::new(dir).is_dir());
+            if !exists {
+                let message = format!("directory {} does not exist", dir);
+                diagnostics.add(Diagnostic { line: line_no, category: Category::MissingDirectory, message }, line);
+            }
+            writer.write(&entry)?;
+            stats.entries += 1;
+        }
+        batch.clear();
+        Ok(())
+    };
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    for line in lines {
+        batch.push(line?);
+        if batch.len() == BATCH_SIZE {
+            flush_batch(&mut batch)?;
+        }
+    }
+    flush_batch(&mut batch)?;
 
-    Ok(db)
+    writer.finish()?;
+    Ok(())
 }
 
-pub fn run(log_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(log_file: &str, options: &GenerateOptions) -> Result<(), Box<dyn std::error::Error>> {
     if !Path::new(log_file).is_absolute() {
         return Err(format!("log file path must be absolute: {}", log_file).into());
     }
-    generate_db(log_file, "compile_commands.json")?;
+    generate_db_with(log_file, "compile_commands.json", options)?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::Value;
     use std::fs;
     use tempfile::TempDir;
 
@@ -208,8 +527,8 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["directory"], "/project");
-            assert_eq!(entry["file"], "/project/main.c");
+            assert_eq!(entry.directory, "/project");
+            assert_eq!(entry.file, "/project/main.c");
         }
 
         #[test]
@@ -218,7 +537,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["file"], "/project/main.cpp");
+            assert_eq!(entry.file, "/project/main.cpp");
         }
 
         #[test]
@@ -227,7 +546,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["file"], "/project/main.cc");
+            assert_eq!(entry.file, "/project/main.cc");
         }
 
         #[test]
@@ -264,8 +583,8 @@ mod tests {
             let result = parse_log_entry(line, Some("/override"));
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["directory"], "/override");
-            assert_eq!(entry["file"], "/override/main.c");
+            assert_eq!(entry.directory, "/override");
+            assert_eq!(entry.file, "/override/main.c");
         }
 
         #[test]
@@ -274,7 +593,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["directory"], "");
+            assert_eq!(entry.directory, "");
         }
 
         #[test]
@@ -283,7 +602,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            let args = entry["arguments"].as_array().unwrap();
+            let args = entry.arguments.as_ref().unwrap();
             assert_eq!(args[0], "/usr/bin/gcc");
             assert_eq!(args[1], "-c");
             assert_eq!(args[2], "main.c");
@@ -296,7 +615,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            let args = entry["arguments"].as_array().unwrap();
+            let args = entry.arguments.as_ref().unwrap();
             assert_eq!(args[0], "clang");
             assert_eq!(args[1], "-c");
             assert_eq!(args[2], "main.c");
@@ -308,7 +627,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            let args = entry["arguments"].as_array().unwrap();
+            let args = entry.arguments.as_ref().unwrap();
             assert_eq!(args[0], "/usr/local/bin/gcc-12");
         }
 
@@ -318,7 +637,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            let args = entry["arguments"].as_array().unwrap();
+            let args = entry.arguments.as_ref().unwrap();
             assert_eq!(args[0], "clang++");
         }
 
@@ -328,7 +647,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["file"], "/project/src/main.c");
+            assert_eq!(entry.file, "/project/src/main.c");
         }
 
         #[test]
@@ -337,7 +656,70 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["file"], "/project/third.c");
+            assert_eq!(entry.file, "/project/third.c");
+        }
+
+        #[test]
+        fn expands_existing_response_file() {
+            let temp_dir = TempDir::new().unwrap();
+            fs::write(temp_dir.path().join("main.rsp"), "-O2 -c \"src/my file.c\"").unwrap();
+            let wd = temp_dir.path().to_str().unwrap();
+            let line = format!(r#"{{"wd":"{}","args":["@main.rsp","-o","main.o"]}}"#, wd);
+            let entry = parse_log_entry(&line, None).unwrap();
+            let args = entry.arguments.as_ref().unwrap();
+            assert_eq!(args[1], "-O2");
+            assert_eq!(args[3], "src/my file.c");
+            assert_eq!(entry.file, format!("{}/src/my file.c", wd));
+        }
+
+        #[test]
+        fn keeps_missing_response_file_reference() {
+            let line = r#"{"wd":"/nonexistent","args":["@gone.rsp","-c","main.c"]}"#;
+            let entry = parse_log_entry(line, None).unwrap();
+            let args = entry.arguments.as_ref().unwrap();
+            assert_eq!(args[1], "@gone.rsp");
+        }
+
+        #[test]
+        fn decodes_hex_encoded_utf8_values() {
+            let line = r#"{"wd":{"hex":"2f70726f6a"},"args":["-c",{"hex":"6d61696e2e63"}]}"#;
+            let entry = parse_log_line(line, None).unwrap();
+            assert_eq!(entry.directory, "/proj");
+            assert_eq!(entry.file, "/proj/main.c");
+        }
+
+        #[test]
+        fn reports_non_utf8_argument() {
+            let line = r#"{"wd":"/project","args":["-c",{"hex":"ff2e63"}]}"#;
+            assert_eq!(parse_log_line(line, None), Err(SkipReason::NotUtf8));
+        }
+
+        #[test]
+        fn reports_non_utf8_directory() {
+            let line = r#"{"wd":{"hex":"2fff"},"args":["-c","main.c"]}"#;
+            assert_eq!(parse_log_line(line, None), Err(SkipReason::NotUtf8));
+        }
+
+        #[test]
+        fn reports_skip_reasons() {
+            assert_eq!(parse_log_line("not json", None), Err(SkipReason::InvalidEntry));
+            assert_eq!(parse_log_line(r#"{"wd":"/p"}"#, None), Err(SkipReason::InvalidEntry));
+            assert_eq!(
+                parse_log_line(r#"{"v":2,"wd":"/p","compiler":"cc","args":["a.c"]}"#, None),
+                Err(SkipReason::UnsupportedVersion(2))
+            );
+            assert_eq!(
+                parse_log_line(r#"{"wd":"/p","args":["-c","start.S"]}"#, None),
+                Err(SkipReason::NoSource)
+            );
+            assert_eq!(
+                parse_log_line(r#"{"wd":"/p","args":["-o","a.out","a.o"]}"#, None),
+                Err(SkipReason::NotCompile)
+            );
+            assert_eq!(
+                parse_log_line(r#"{"wd":"/p","args":["--version"]}"#, None),
+                Err(SkipReason::NotCompile)
+            );
         }
 
         #[test]
@@ -346,7 +728,7 @@ mod tests {
             let result = parse_log_entry(line, None);
             assert!(result.is_some());
             let entry = result.unwrap();
-            assert_eq!(entry["file"], "/project/main.c");
+            assert_eq!(entry.file, "/project/main.c");
         }
     }
 
@@ -361,6 +743,10 @@ mod tests {
             log_path.to_string_lossy().to_string()
         }
 
+        fn read_db(dst: &Path) -> Vec<Value> {
+            serde_json::from_str(&fs::read_to_string(dst).unwrap()).unwrap()
+        }
+
         #[test]
         fn generates_empty_db_for_empty_log() {
             let temp_dir = TempDir::new().unwrap();
@@ -369,7 +755,7 @@ mod tests {
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             assert!(db.is_empty());
 
             let content = fs::read_to_string(&dst).unwrap();
@@ -385,7 +771,7 @@ mod tests {
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             assert_eq!(db.len(), 1);
             assert_eq!(db[0]["directory"], "/project");
             assert_eq!(db[0]["file"], "/project/main.c");
@@ -402,7 +788,7 @@ mod tests {
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             assert_eq!(db.len(), 3);
             assert_eq!(db[0]["file"], "/project/main.c");
             assert_eq!(db[1]["file"], "/project/util.c");
@@ -420,7 +806,7 @@ mod tests {
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             assert_eq!(db.len(), 2);
         }
 
@@ -435,7 +821,7 @@ not valid json
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             assert_eq!(db.len(), 2);
         }
 
@@ -474,7 +860,7 @@ not valid json
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             assert_eq!(db.len(), 3);
             assert!(db[0]["file"].as_str().unwrap().ends_with(".c"));
             assert!(db[1]["file"].as_str().unwrap().ends_with(".cc"));
@@ -490,7 +876,7 @@ not valid json
 
             let result = generate_db(&log_file, dst.to_str().unwrap());
             assert!(result.is_ok());
-            let db = result.unwrap();
+            let db = read_db(&dst);
             let args = db[0]["arguments"].as_array().unwrap();
             assert!(args.iter().any(|a| a == "-Wall"));
             assert!(args.iter().any(|a| a == "-O2"));
@@ -499,6 +885,287 @@ not valid json
         }
     }
 
+    // ==================== streaming tests ====================
+
+    mod streaming_tests {
+        use super::*;
+        use std::fs::File;
+        use std::io::BufWriter;
+
+        fn synthetic_log(dir: &TempDir, lines: usize) -> String {
+            let log_path = dir.path().join("cc_hook.txt");
+            let mut out = BufWriter::new(File::create(&log_path).unwrap());
+            for i in 0..lines {
+                writeln!(
+                    out,
+                    r#"{{"v":1,"wd":"/project/dir{}","compiler":"clang","args":["-c","-O2","-Wall","-Iinclude","-DN={}","src/file{}.c","-o","file{}.o"]}}"#,
+                    i % 100, i, i, i
+                ).unwrap();
+            }
+            log_path.to_string_lossy().to_string()
+        }
+
+        #[test]
+        fn output_matches_pretty_printed_array() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_file = synthetic_log(&temp_dir, 3);
+            let dst = temp_dir.path().join("compile_commands.json");
+
+            generate_db(&log_file, dst.to_str().unwrap()).unwrap();
+
+            let content = fs::read_to_string(&dst).unwrap();
+            let db: Vec<CompileCommand> = serde_json::from_str(&content).unwrap();
+            assert_eq!(content, serde_json::to_string_pretty(&db).unwrap());
+        }
+
+        #[test]
+        fn drops_duplicate_entries() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, r#"{"wd":"/p","args":["-c","a.c"]}
+{"wd":"/p","args":["-c","b.c"]}
+{"wd":"/p","args":["-c","a.c"]}
+{"wd":"/p","args":["-c","a.c","-O2"]}"#).unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+
+            let stats = generate_db(log_path.to_str().unwrap(), dst.to_str().unwrap()).unwrap();
+
+            assert_eq!(stats, GenerateStats { entries: 3, skipped: 0, duplicates: 1 });
+            let db: Vec<Value> = serde_json::from_str(&fs::read_to_string(&dst).unwrap()).unwrap();
+            assert_eq!(db.len(), 3);
+        }
+
+        #[test]
+        fn counts_skipped_lines() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, "{\"wd\":\"/p\",\"args\":[\"-c\",\"a.c\"]}\nbroken\n").unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+
+            let stats = generate_db(log_path.to_str().unwrap(), dst.to_str().unwrap()).unwrap();
+
+            assert_eq!(stats, GenerateStats { entries: 1, skipped: 1, duplicates: 0 });
+        }
+
+        #[test]
+        fn parallel_parsing_preserves_order() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_file = synthetic_log(&temp_dir, BATCH_SIZE + 123);
+            let sequential = temp_dir.path().join("sequential.json");
+            let parallel = temp_dir.path().join("parallel.json");
+
+            generate_db_with(&log_file, sequential.to_str().unwrap(), &GenerateOptions { jobs: 1, ..GenerateOptions::default() }).unwrap();
+            let stats = generate_db_with(&log_file, parallel.to_str().unwrap(), &GenerateOptions { jobs: 8, ..GenerateOptions::default() }).unwrap();
+
+            assert_eq!(stats.entries, BATCH_SIZE + 123);
+            assert_eq!(fs::read(&sequential).unwrap(), fs::read(&parallel).unwrap());
+        }
+
+        #[test]
+        fn streams_large_synthetic_log() {
+            let temp_dir = TempDir::new().unwrap();
+            let lines = 50_000;
+            let log_file = synthetic_log(&temp_dir, lines);
+            let dst = temp_dir.path().join("compile_commands.json");
+
+            let stats = generate_db(&log_file, dst.to_str().unwrap()).unwrap();
+
+            assert_eq!(stats.entries, lines);
+            let db: Vec<Value> = serde_json::from_str(&fs::read_to_string(&dst).unwrap()).unwrap();
+            assert_eq!(db.len(), lines);
+            assert_eq!(db[lines - 1]["file"], format!("/project/dir{}/src/file{}.c", (lines - 1) % 100, lines - 1));
+        }
+
+        /// Benchmark: run with `cargo test --release -- --ignored --nocapture bench_`.
+        #[test]
+        #[ignore]
+        fn bench_generate_two_million_lines() {
+            let temp_dir = TempDir::new().unwrap();
+            let lines = 2_000_000;
+            let log_file = synthetic_log(&temp_dir, lines);
+            let dst = temp_dir.path().join("compile_commands.json");
+
+            for jobs in [1, GenerateOptions::default().jobs] {
+                let start = std::time::Instant::now();
+                let stats = generate_db_with(&log_file, dst.to_str().unwrap(), &GenerateOptions { jobs, ..GenerateOptions::default() }).unwrap();
+                assert_eq!(stats.entries, lines);
+                println!("jobs={}: {} entries in {:?}", jobs, stats.entries, start.elapsed());
+            }
+        }
+    }
+
+    // ==================== diagnostics tests ====================
+
+    mod diagnostics_tests {
+        use super::*;
+
+        fn quiet() -> GenerateOptions {
+            GenerateOptions { verbosity: Verbosity::Quiet, ..GenerateOptions::default() }
+        }
+
+        #[test]
+        fn parses_generate_options() {
+            let options = parse_args(&["--generate", "--verbose", "--strict", "--report", "r.json"]).unwrap();
+            assert_eq!(options.verbosity, Verbosity::Verbose);
+            assert!(options.strict);
+            assert_eq!(options.report, Some(PathBuf::from("r.json")));
+
+            let options = parse_args(&["--quiet", "--report=out.json"]).unwrap();
+            assert_eq!(options.verbosity, Verbosity::Quiet);
+            assert!(!options.strict);
+            assert_eq!(options.report, Some(PathBuf::from("out.json")));
+        }
+
+        #[test]
+        fn parses_query_builtins() {
+            assert!(!parse_args(&["--generate"]).unwrap().query_builtins);
+            assert!(parse_args(&["--generate", "--query-builtins"]).unwrap().query_builtins);
+        }
+
+        #[test]
+        fn parses_compat_rules() {
+            assert!(parse_args(&["--generate"]).unwrap().compat.is_none());
+            let options = parse_args(&["--gcc-compat"]).unwrap();
+            let table = options.compat.unwrap().rules.len();
+
+            let options = parse_args(&["--compat-rule", "-mfoo", "--compat-rule=-mbar=* => -mbar"]).unwrap();
+            let compat = options.compat.unwrap();
+            assert_eq!(compat.rules.len(), table + 2);
+            assert_eq!(compat.rewrite_args(&["gcc".to_string(), "-mfoo".to_string()]), vec!["gcc"]);
+            assert!(parse_args(&["--compat-rule", "bogus"]).unwrap_err().contains("FLAG"));
+        }
+
+        #[test]
+        fn parses_path_mappings() {
+            let options = parse_args(&["--map", "/build=/home/me", "--map=/opt=/usr", "--no-canonicalize"]).unwrap();
+            assert_eq!(options.paths.maps.len(), 2);
+            assert_eq!(options.paths.maps[1].to, PathBuf::from("/usr"));
+            assert!(!options.paths.canonicalize);
+            assert!(parse_args(&["--map", "nothing"]).unwrap_err().contains("FROM=TO"));
+        }
+
+        #[test]
+        fn rejects_unknown_options() {
+            assert!(parse_args(&["--generate", "--bogus"]).unwrap_err().contains("--bogus"));
+            assert!(parse_args(&["--report"]).is_err());
+        }
+
+        #[test]
+        fn reports_line_numbers_and_categories() {
+            let temp_dir = TempDir::new().unwrap();
+            let dir = temp_dir.path().to_str().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, format!(
+                "{{\"wd\":\"{dir}\",\"args\":[\"-c\",\"a.c\"]}}\n\
+                 garbage\n\
+                 {{\"wd\":\"{dir}\",\"args\":[\"-o\",\"app\",\"a.o\"]}}\n\
+                 {{\"wd\":\"{dir}\",\"args\":[\"-c\",\"a.c\"]}}\n\
+                 {{\"wd\":\"/nonexistent\",\"args\":[\"-c\",\"b.c\"]}}\n"
+            )).unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+            let report = temp_dir.path().join("report.json");
+            let options = GenerateOptions { report: Some(report.clone()), ..quiet() };
+
+            let stats = generate_db_with(log_path.to_str().unwrap(), dst.to_str().unwrap(), &options).unwrap();
+
+            assert_eq!(stats, GenerateStats { entries: 2, skipped: 2, duplicates: 1 });
+            let report: Value = serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+            assert_eq!(report["lines"], 5);
+            let found: Vec<(u64, &str)> = report["diagnostics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|d| (d["line"].as_u64().unwrap(), d["category"].as_str().unwrap()))
+                .collect();
+            assert_eq!(
+                found,
+                vec![(2, "invalid_entry"), (3, "not_compile"), (4, "duplicate"), (5, "missing_directory")]
+            );
+        }
+
+        #[test]
+        fn normalizes_and_maps_paths() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, r#"{"wd":"/build/src/out","args":["-I/build/src/out/../include","-c","../lib/a.c"]}"#).unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+            let mut options = quiet();
+            options.paths.maps.push("/build/src=/home/me/src".parse().unwrap());
+
+            generate_db_with(log_path.to_str().unwrap(), dst.to_str().unwrap(), &options).unwrap();
+
+            let db: Vec<CompileCommand> = serde_json::from_str(&fs::read_to_string(&dst).unwrap()).unwrap();
+            assert_eq!(db[0].directory, "/home/me/src/out");
+            assert_eq!(db[0].file, "/home/me/src/lib/a.c");
+            assert_eq!(db[0].arguments.as_ref().unwrap()[1], "-I/home/me/src/include");
+            assert_eq!(db[0].arguments.as_ref().unwrap()[3], "../lib/a.c");
+        }
+
+        #[test]
+        fn strict_mode_fails_on_malformed_lines() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, "{\"wd\":\"/p\",\"args\":[\"-c\",\"a.c\"]}\n{\"wd\":\n").unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+            fs::write(&dst, "[]").unwrap();
+            let options = GenerateOptions { strict: true, ..quiet() };
+
+            let result = generate_db_with(log_path.to_str().unwrap(), dst.to_str().unwrap(), &options);
+
+            assert!(result.unwrap_err().to_string().contains("1 malformed log lines"));
+            assert_eq!(fs::read_to_string(&dst).unwrap(), "[]");
+        }
+
+        #[test]
+        fn reports_lines_with_invalid_utf8() {
+            let temp_dir = TempDir::new().unwrap();
+            let dir = temp_dir.path().to_str().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            let mut log = format!("{{\"wd\":\"{dir}\",\"args\":[\"-c\",\"a.c\"]}}\n").into_bytes();
+            log.extend(b"{\"wd\":\"\xff\"}\n");
+            log.extend(format!("{{\"wd\":\"{dir}\",\"args\":[\"-c\",\"b.c\"]}}\n").into_bytes());
+            fs::write(&log_path, log).unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+            let report = temp_dir.path().join("report.json");
+            let options = GenerateOptions { report: Some(report.clone()), ..quiet() };
+
+            let stats = generate_db_with(log_path.to_str().unwrap(), dst.to_str().unwrap(), &options).unwrap();
+
+            assert_eq!(stats, GenerateStats { entries: 2, skipped: 1, duplicates: 0 });
+            let report: Value = serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+            assert_eq!(report["diagnostics"][0]["line"], 2);
+            assert_eq!(report["diagnostics"][0]["category"], "invalid_entry");
+        }
+
+        #[test]
+        fn strict_mode_fails_on_invalid_utf8_lines() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, b"{\"wd\":\"/p\",\"args\":[\"-c\",\"a\xff.c\"]}\n").unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+            fs::write(&dst, "[]").unwrap();
+            let options = GenerateOptions { strict: true, ..quiet() };
+
+            let result = generate_db_with(log_path.to_str().unwrap(), dst.to_str().unwrap(), &options);
+
+            assert!(result.unwrap_err().to_string().contains("1 malformed log lines"));
+            assert_eq!(fs::read_to_string(&dst).unwrap(), "[]");
+        }
+
+        #[test]
+        fn strict_mode_accepts_skipped_link_steps() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+            fs::write(&log_path, "{\"wd\":\"/p\",\"args\":[\"-c\",\"a.c\"]}\n{\"wd\":\"/p\",\"args\":[\"-o\",\"app\"]}\n").unwrap();
+            let dst = temp_dir.path().join("compile_commands.json");
+            let options = GenerateOptions { strict: true, ..quiet() };
+
+            let stats = generate_db_with(log_path.to_str().unwrap(), dst.to_str().unwrap(), &options).unwrap();
+
+            assert_eq!(stats.entries, 1);
+        }
+    }
+
     // ==================== run function tests ====================
 
     mod run_tests {
@@ -517,7 +1184,7 @@ not valid json
             let log_content = r#"{"wd":"/project","args":["-c","main.c"]}"#;
             fs::write(&log_path, log_content).unwrap();
 
-            let result = run(log_path.to_str().unwrap());
+            let result = run(log_path.to_str().unwrap(), &GenerateOptions::default());
             assert!(result.is_ok());
             assert!(temp_dir.path().join("compile_commands.json").exists());
 
@@ -527,7 +1194,7 @@ not valid json
 
         #[test]
         fn run_rejects_relative_path() {
-            let result = run("cc_hook.txt");
+            let result = run("cc_hook.txt", &GenerateOptions::default());
             assert!(result.is_err());
             let err = result.unwrap_err().to_string();
             assert!(err.contains("absolute"));
@@ -535,7 +1202,7 @@ not valid json
 
         #[test]
         fn run_returns_error_for_missing_log() {
-            let result = run("/nonexistent/cc_hook.txt");
+            let result = run("/nonexistent/cc_hook.txt", &GenerateOptions::default());
             assert!(result.is_err());
         }
     }
@@ -1,7 +1,17 @@
 pub mod wrapper;
 pub mod generate;
+pub mod rsp;
+pub mod log;
+pub mod diagnostics;
+pub mod append;
+pub mod builtins;
+
+pub use compdb::{encoding, output, schema};
+pub use wrapper::log_command;
 
 use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::path::Path;
 
 /// Environment variable name for the log file path.
@@ -12,6 +22,12 @@ pub const ENV_COMPDB_GENERATE: &str = "COMPDB_GENERATE";
 pub const ENV_COMPDB_CC: &str = "COMPDB_CC";
 /// Environment variable name for the C++ compiler.
 pub const ENV_COMPDB_CXX: &str = "COMPDB_CXX";
+/// Environment variable name for failing the build when logging fails.
+pub const ENV_COMPDB_STRICT: &str = "COMPDB_STRICT";
+/// Environment variable name for the file that collects logging errors.
+pub const ENV_COMPDB_ERROR_LOG: &str = "COMPDB_ERROR_LOG";
+/// Environment variable name for the encoding of new log records.
+pub const ENV_COMPDB_LOG_FORMAT: &str = "COMPDB_LOG_FORMAT";
 
 /// Error type for log file path validation.
 #[derive(Debug, PartialEq)]
@@ -22,13 +38,22 @@ pub enum LogFileError {
     NotAbsolute,
 }
 
+impl fmt::Display for LogFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFileError::NotSet => write!(f, "{} environment variable is required", ENV_COMPDB_LOG),
+            LogFileError::NotAbsolute => write!(f, "{} must be an absolute path", ENV_COMPDB_LOG),
+        }
+    }
+}
+
 pub fn run_cc() {
-    let compiler = env::var(ENV_COMPDB_CC).unwrap_or_else(|_| "clang".to_string());
+    let compiler = env::var_os(ENV_COMPDB_CC).unwrap_or_else(|| "clang".into());
     run_with_compiler(&compiler);
 }
 
 pub fn run_cxx() {
-    let compiler = env::var(ENV_COMPDB_CXX).unwrap_or_else(|_| "clang++".to_string());
+    let compiler = env::var_os(ENV_COMPDB_CXX).unwrap_or_else(|| "clang++".into());
     run_with_compiler(&compiler);
 }
 
@@ -55,8 +80,8 @@ pub fn get_log_file() -> Result<String, LogFileError> {
 }
 
 /// Check if generate mode is requested via --generate flag in args.
-pub fn has_generate_flag(args: &[String]) -> bool {
-    args.iter().any(|a| a == "--generate")
+pub fn has_generate_flag<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.iter().any(|a| a.as_ref() == "--generate")
 }
 
 /// Check if generate mode is requested via COMPDB_GENERATE environment variable.
@@ -66,32 +91,48 @@ pub fn has_generate_env() -> bool {
         .unwrap_or(false)
 }
 
+/// Check if strict mode is requested via COMPDB_STRICT environment variable.
+/// In strict mode the wrapper fails the build when the command cannot be logged.
+pub fn has_strict_env() -> bool {
+    env::var(ENV_COMPDB_STRICT)
+        .map(|v| !v.is_empty())
+        .unwrap_or(false)
+}
+
 /// Determine if generate mode should be used.
-pub fn should_generate(args: &[String]) -> bool {
+pub fn should_generate<S: AsRef<OsStr>>(args: &[S]) -> bool {
     has_generate_flag(args) || has_generate_env()
 }
 
-fn run_with_compiler(compiler: &str) {
-    let args: Vec<String> = env::args().collect();
-    let log_file = match get_log_file() {
-        Ok(path) => path,
-        Err(LogFileError::NotSet) => {
-            eprintln!("Error: {} environment variable is required", ENV_COMPDB_LOG);
-            std::process::exit(1);
-        }
-        Err(LogFileError::NotAbsolute) => {
-            eprintln!("Error: {} must be an absolute path", ENV_COMPDB_LOG);
+fn run_with_compiler(compiler: &OsStr) {
+    let args: Vec<OsString> = env::args_os().collect();
+    let log_file = get_log_file();
+
+    if append::has_append_flag(&args) {
+        let result = log_file
+            .map_err(|e| e.to_string())
+            .and_then(|log_file| append::run(&log_file, &args[2..]));
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
-    };
-
-    if should_generate(&args) {
-        if let Err(e) = generate::run(&log_file) {
+    } else if should_generate(&args) {
+        let log_file = match log_file {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let result = generate::parse_args(&args[1..])
+            .map_err(|e| e.into())
+            .and_then(|options| generate::run(&log_file, &options));
+        if let Err(e) = result {
             eprintln!("Error: {}", e);
             std::process::exit(1);
         }
     } else {
-        wrapper::run(&log_file, compiler);
+        wrapper::run(log_file, compiler);
     }
 }
 
@@ -334,6 +375,40 @@ mod tests {
         }
     }
 
+    // ==================== has_strict_env tests ====================
+
+    mod has_strict_env_tests {
+        use super::*;
+        use std::sync::Mutex;
+
+        static ENV_MUTEX: Mutex<()> = Mutex::new(());
+
+        #[test]
+        fn returns_false_when_env_not_set() {
+            let _guard = ENV_MUTEX.lock().unwrap();
+            env::remove_var(ENV_COMPDB_STRICT);
+            assert!(!has_strict_env());
+        }
+
+        #[test]
+        fn returns_true_when_env_is_set() {
+            let _guard = ENV_MUTEX.lock().unwrap();
+            env::set_var(ENV_COMPDB_STRICT, "1");
+            let result = has_strict_env();
+            env::remove_var(ENV_COMPDB_STRICT);
+            assert!(result);
+        }
+
+        #[test]
+        fn returns_false_when_env_is_empty() {
+            let _guard = ENV_MUTEX.lock().unwrap();
+            env::set_var(ENV_COMPDB_STRICT, "");
+            let result = has_strict_env();
+            env::remove_var(ENV_COMPDB_STRICT);
+            assert!(!result);
+        }
+    }
+
     // ==================== should_generate tests ====================
 
     mod should_generate_tests {
@@ -0,0 +1,601 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use fs2::FileExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::ENV_COMPDB_LOG_FORMAT;
+
+/// File extension of a single log record in a shard directory.
+const SHARD_EXTENSION: &str = "json";
+/// File extension of a zstd-compressed log record in a shard directory.
+const ZSTD_SHARD_EXTENSION: &str = "zst";
+/// File in a shard directory holding the dictionary of its zstd-compressed shards.
+const DICTIONARY_FILE: &str = "zstd.dict";
+/// Magic number at the start of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Compression level for zstd logs; higher levels cost the build more time per entry.
+const ZSTD_LEVEL: i32 = 3;
+/// Key of the header record that starts a compressed single-file log.
+pub const HEADER_KEY: &str = "compdb_log";
+/// Version of the compressed log container written in the header.
+/// Version 1 logs compress every record on its own; from version 2 on, the first record
+/// is the dictionary of all later ones.
+pub const CONTAINER_VERSION: u64 = 2;
+
+/// Encoding of the records in a log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// One JSON object per line.
+    Json,
+    /// JSON lines where every record is its own zstd frame, compressed against the first
+    /// record of the log, which shares the compiler and most flags with later records.
+    /// Frames can be appended without touching earlier data.
+    Zstd,
+}
+
+impl LogFormat {
+    /// Parse a format name as accepted by the COMPDB_LOG_FORMAT environment variable.
+    pub fn parse(name: &str) -> Result<LogFormat, String> {
+        match name {
+            "" | "json" => Ok(LogFormat::Json),
+            "zstd" => Ok(LogFormat::Zstd),
+            other => Err(format!("unknown {} '{}', expected 'json' or 'zstd'", ENV_COMPDB_LOG_FORMAT, other)),
+        }
+    }
+
+    /// Determine the format for new records from the COMPDB_LOG_FORMAT environment variable.
+    pub fn from_env() -> Result<LogFormat, String> {
+        LogFormat::parse(&env::var(ENV_COMPDB_LOG_FORMAT).unwrap_or_default())
+    }
+
+    /// Encode a record as a line, compressing it into its own frame for zstd, using the
+    /// dictionary if there is one.
+    fn encode<T: Serialize + ?Sized>(self, entry: &T, dictionary: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let line = to_line(entry)?;
+        match (self, dictionary) {
+            (LogFormat::Json, _) => Ok(line),
+            (LogFormat::Zstd, None) => zstd::bulk::compress(&line, ZSTD_LEVEL),
+            (LogFormat::Zstd, Some(dictionary)) => {
+                zstd::bulk::Compressor::with_dictionary(ZSTD_LEVEL, dictionary)?.compress(&line)
+            }
+        }
+    }
+}
+
+/// Serialize a record as a JSON line, with fields in the order of its type.
+fn to_line<T: Serialize + ?Sized>(entry: &T) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Check if a line is the header record of a compressed log rather than an entry.
+pub fn is_header(line: &[u8]) -> bool {
+    line.starts_with(format!("{{\"{}\"", HEADER_KEY).as_bytes())
+}
+
+/// Sequence number distinguishing shards written by the same process.
+static SHARD_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Check if the log path selects sharded storage.
+/// That is the case for an existing directory or a path ending with `/`.
+pub fn is_shard_dir(log_path: &Path) -> bool {
+    log_path.is_dir() || log_path.as_os_str().as_bytes().ends_with(b"/")
+}
+
+/// Path of the lock file guarding a single-file log, e.g. `build.log.lock`.
+pub fn lock_path(log_path: &Path) -> PathBuf {
+    let mut path = log_path.as_os_str().to_os_string();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+/// Append a log entry to the log at `log_path`.
+/// A directory stores every entry in its own shard file written atomically, so no lock
+/// is needed; anything else is a single file of records guarded by a lock file.
+pub fn append_entry<T: Serialize + ?Sized>(log_path: &Path, entry: &T, format: LogFormat) -> Result<(), String> {
+    if is_shard_dir(log_path) {
+        write_shard(log_path, entry, format)
+    } else {
+        append_record(log_path, entry, format)
+    }
+}
+
+fn append_record<T: Serialize + ?Sized>(log_path: &Path, entry: &T, format: LogFormat) -> Result<(), String> {
+    let lock_file_path = lock_path(log_path);
+
+    // Create or open the lock file
+    let lock_file = File::create(&lock_file_path)
+        .map_err(|e| format!("failed to create lock file {}: {}", lock_file_path.display(), e))?;
+
+    // Acquire an exclusive lock, released when the lock file is closed
+    lock_file.lock_exclusive()
+        .map_err(|e| format!("failed to acquire lock {}: {}", lock_file_path.display(), e))?;
+
+    // Open log file in append mode
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(log_path)
+        .map_err(|e| format!("failed to open log file {}: {}", log_path.display(), e))?;
+
+    let write_error = |e: io::Error| format!("failed to write to log file {}: {}", log_path.display(), e);
+
+    // Keep appending in the encoding the file already has, so a file is never mixed
+    let (format, dictionary) = match sniff_format(&mut file).map_err(write_error)? {
+        Some(LogFormat::Zstd) => (LogFormat::Zstd, read_dictionary(&file).map_err(write_error)?),
+        Some(existing) => (existing, None),
+        None if format == LogFormat::Zstd => {
+            // The header and the first record, which becomes the dictionary, go out together
+            let header = json!({ HEADER_KEY: CONTAINER_VERSION, "encoding": "zstd" });
+            let mut records = format.encode(&header, None).map_err(write_error)?;
+            records.extend(format.encode(entry, None).map_err(write_error)?);
+            return file.write_all(&records).map_err(write_error);
+        }
+        None => (format, None),
+    };
+
+    // Write log entry in a single call so a record is never interleaved with another
+    file.write_all(&format.encode(entry, dictionary.as_deref()).map_err(write_error)?).map_err(write_error)
+}
+
+/// Version of a compressed log's header record, 0 if it isn't one.
+fn header_version(line: &[u8]) -> u64 {
+    serde_json::from_slice::<Value>(line)
+        .ok()
+        .and_then(|header| header.get(HEADER_KEY)?.as_u64())
+        .unwrap_or(0)
+}
+
+/// Decode the next zstd frame, returning its content, or None at the end of the input,
+/// and the reader positioned at the following frame.
+fn read_frame<R: BufRead>(mut reader: R) -> io::Result<(Option<Vec<u8>>, R)> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok((None, reader));
+    }
+    let mut decoder = zstd::Decoder::with_buffer(reader)?.single_frame();
+    let mut content = Vec::new();
+    decoder.read_to_end(&mut content)?;
+    Ok((Some(content), decoder.finish()))
+}
+
+/// Read the dictionary of a compressed log file, the record after a version 2 header.
+/// Version 1 logs, and logs without records yet, have none.
+fn read_dictionary(file: &File) -> io::Result<Option<Vec<u8>>> {
+    let (header, reader) = read_frame(BufReader::new(file))?;
+    if header.is_none_or(|header| header_version(&header) < 2) {
+        return Ok(None);
+    }
+    Ok(read_frame(reader)?.0)
+}
+
+/// Read the dictionary of the compressed shards in a shard directory. The first writer
+/// publishes its record as the dictionary; linking it into place fails if another writer
+/// was first, so every shard is compressed against the same dictionary. Without one,
+/// e.g. on filesystems without hard links, shards are compressed on their own.
+fn shard_dictionary(dir: &Path, line: &[u8], shard_name: &str) -> Option<Vec<u8>> {
+    let path = dir.join(DICTIONARY_FILE);
+    if let Ok(dictionary) = fs::read(&path) {
+        return Some(dictionary);
+    }
+    let tmp_path = dir.join(format!(".{}.{}.tmp", DICTIONARY_FILE, shard_name));
+    fs::write(&tmp_path, line).ok()?;
+    let linked = fs::hard_link(&tmp_path, &path);
+    let _ = fs::remove_file(&tmp_path);
+    match linked {
+        Ok(()) => Some(line.to_vec()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => fs::read(&path).ok(),
+        Err(_) => None,
+    }
+}
+
+/// Determine the encoding of an existing log file from its first bytes.
+/// Returns None for an empty file.
+fn sniff_format(file: &mut File) -> io::Result<Option<LogFormat>> {
+    let mut magic = [0u8; 4];
+    let mut len = 0;
+    file.seek(SeekFrom::Start(0))?;
+    while len < magic.len() {
+        match file.read(&mut magic[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(match len {
+        0 => None,
+        4 if magic == ZSTD_MAGIC => Some(LogFormat::Zstd),
+        _ => Some(LogFormat::Json),
+    })
+}
+
+/// Write an entry to a uniquely named shard file.
+/// The record is written under a hidden temporary name and renamed into place,
+/// which is atomic on local filesystems and NFS alike.
+fn write_shard<T: Serialize + ?Sized>(dir: &Path, entry: &T, format: LogFormat) -> Result<(), String> {
+    fs::create_dir_all(dir)
+        .map_err(|e| format!("failed to create log directory {}: {}", dir.display(), e))?;
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let seq = SHARD_SEQ.fetch_add(1, Ordering::Relaxed);
+    let mut name = format!("{:020}-{}-{}.{}", nanos, std::process::id(), seq, SHARD_EXTENSION);
+    if format == LogFormat::Zstd {
+        name = format!("{}.{}", name, ZSTD_SHARD_EXTENSION);
+    }
+    let tmp_path = dir.join(format!(".{}.tmp", name));
+    let shard_path = dir.join(&name);
+    let dictionary = match format {
+        LogFormat::Zstd => to_line(entry).ok().and_then(|line| shard_dictionary(dir, &line, &name)),
+        LogFormat::Json => None,
+    };
+
+    let mut file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(&tmp_path)
+        .map_err(|e| format!("failed to create log shard {}: {}", tmp_path.display(), e))?;
+    format.encode(entry, dictionary.as_deref())
+        .and_then(|record| file.write_all(&record))
+        .map_err(|e| format!("failed to write log shard {}: {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, &shard_path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("failed to rename log shard to {}: {}", shard_path.display(), e)
+    })
+}
+
+/// List the shard files of a shard directory in the order they were written.
+/// Hidden files, such as shards still being written, are skipped.
+pub fn list_shards(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_shard = !name.as_bytes().starts_with(b".")
+            && Path::new(&name)
+                .extension()
+                .is_some_and(|ext| ext == SHARD_EXTENSION || ext == ZSTD_SHARD_EXTENSION);
+        if is_shard && entry.file_type()?.is_file() {
+            shards.push(entry.path());
+        }
+    }
+    // Shard names start with a zero-padded timestamp, so name order is write order
+    shards.sort();
+    Ok(shards)
+}
+
+type Lines = Box<dyn Iterator<Item = io::Result<Vec<u8>>>>;
+
+/// Read all lines of a log, whether it is a single file or a shard directory.
+/// Compressed files are decoded transparently and header records are skipped.
+/// Lines are returned as raw bytes, so a line that is not valid UTF-8 doesn't end reading.
+pub fn read_lines(log_path: &Path) -> io::Result<Lines> {
+    let lines: Lines = if log_path.is_dir() {
+        let shards = list_shards(log_path)?;
+        let dictionary = fs::read(log_path.join(DICTIONARY_FILE)).ok();
+        Box::new(shards.into_iter().flat_map(move |shard| {
+            open_shard_lines(&shard, dictionary.as_deref()).unwrap_or_else(|e| Box::new(std::iter::once(Err(e))))
+        }))
+    } else {
+        open_lines(log_path)?
+    };
+    Ok(Box::new(lines.filter(|line| !matches!(line, Ok(line) if is_header(line)))))
+}
+
+fn open_shard_lines(path: &Path, dictionary: Option<&[u8]>) -> io::Result<Lines> {
+    let mut file = File::open(path)?;
+    match (sniff_format(&mut file)?, dictionary) {
+        // Shards written before the dictionary existed decode with it all the same
+        (Some(LogFormat::Zstd), Some(dictionary)) => {
+            let decoder = zstd::Decoder::with_dictionary(BufReader::new(file), dictionary)?;
+            Ok(byte_lines(BufReader::new(decoder)))
+        }
+        (Some(LogFormat::Zstd), None) => Ok(byte_lines(BufReader::new(zstd::Decoder::new(file)?))),
+        _ => Ok(byte_lines(BufReader::new(file))),
+    }
+}
+
+fn open_lines(path: &Path) -> io::Result<Lines> {
+    let mut file = File::open(path)?;
+    match sniff_format(&mut file)? {
+        Some(LogFormat::Zstd) => zstd_lines(file),
+        _ => Ok(byte_lines(BufReader::new(file))),
+    }
+}
+
+/// Split a reader into lines without their line endings.
+fn byte_lines<R: BufRead + 'static>(reader: R) -> Lines {
+    Box::new(reader.split(b'\n').map(|line| {
+        line.map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            line
+        })
+    }))
+}
+
+/// Decode the lines of a compressed log file: the header, then the records, which
+/// are compressed against the first one from version 2 on.
+fn zstd_lines(file: File) -> io::Result<Lines> {
+    let (Some(header), reader) = read_frame(BufReader::new(file))? else {
+        return Ok(Box::new(std::iter::empty()));
+    };
+    let (dictionary, mut reader) = match header_version(&header) {
+        0 | 1 => (None, reader),
+        _ => read_frame(reader)?,
+    };
+    let leading = byte_lines(Cursor::new(header).chain(Cursor::new(dictionary.clone().unwrap_or_default())));
+    if reader.fill_buf()?.is_empty() {
+        return Ok(Box::new(leading));
+    }
+    let decoder = match &dictionary {
+        Some(dictionary) => zstd::Decoder::with_dictionary(reader, dictionary)?,
+        None => zstd::Decoder::with_buffer(reader)?,
+    };
+    Ok(Box::new(leading.chain(byte_lines(BufReader::new(decoder)))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn collect_lines(log_path: &Path) -> Vec<String> {
+        read_lines(log_path).unwrap().map(|l| String::from_utf8(l.unwrap()).unwrap()).collect()
+    }
+
+    mod lock_path_tests {
+        use super::*;
+
+        #[test]
+        fn appends_lock_suffix() {
+            assert_eq!(lock_path(Path::new("/tmp/build.log")), Path::new("/tmp/build.log.lock"));
+        }
+
+        #[test]
+        fn does_not_collide_for_different_extensions() {
+            assert_ne!(lock_path(Path::new("/tmp/build.log")), lock_path(Path::new("/tmp/build.txt")));
+        }
+    }
+
+    mod single_file_tests {
+        use super::*;
+
+        #[test]
+        fn appends_entries_as_lines() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+
+            append_entry(&log_path, &json!({"wd": "/a"}), LogFormat::Json).unwrap();
+            append_entry(&log_path, &json!({"wd": "/b"}), LogFormat::Json).unwrap();
+
+            assert_eq!(collect_lines(&log_path), vec![r#"{"wd":"/a"}"#, r#"{"wd":"/b"}"#]);
+            assert!(temp_dir.path().join("cc_hook.txt.lock").exists());
+        }
+
+        #[test]
+        fn fails_for_missing_directory() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("missing").join("cc_hook.txt");
+            assert!(append_entry(&log_path, &json!({}), LogFormat::Json).is_err());
+        }
+
+        #[test]
+        fn read_fails_for_missing_file() {
+            let temp_dir = TempDir::new().unwrap();
+            assert!(read_lines(&temp_dir.path().join("missing.txt")).is_err());
+        }
+    }
+
+    mod shard_dir_tests {
+        use super::*;
+
+        #[test]
+        fn detects_shard_dir() {
+            let temp_dir = TempDir::new().unwrap();
+            assert!(is_shard_dir(temp_dir.path()));
+            assert!(is_shard_dir(Path::new("/nonexistent/logs/")));
+            assert!(!is_shard_dir(&temp_dir.path().join("cc_hook.txt")));
+        }
+
+        #[test]
+        fn writes_one_shard_per_entry() {
+            let temp_dir = TempDir::new().unwrap();
+
+            append_entry(temp_dir.path(), &json!({"wd": "/a"}), LogFormat::Json).unwrap();
+            append_entry(temp_dir.path(), &json!({"wd": "/b"}), LogFormat::Json).unwrap();
+
+            let shards = list_shards(temp_dir.path()).unwrap();
+            assert_eq!(shards.len(), 2);
+            assert!(!temp_dir.path().join(".lock").exists());
+        }
+
+        #[test]
+        fn reads_shards_in_write_order() {
+            let temp_dir = TempDir::new().unwrap();
+            for i in 0..5 {
+                append_entry(temp_dir.path(), &json!({"n": i}), LogFormat::Json).unwrap();
+            }
+
+            let lines = collect_lines(temp_dir.path());
+            let expected: Vec<String> = (0..5).map(|i| format!(r#"{{"n":{}}}"#, i)).collect();
+            assert_eq!(lines, expected);
+        }
+
+        #[test]
+        fn creates_directory_for_trailing_slash() {
+            let temp_dir = TempDir::new().unwrap();
+            let dir = format!("{}/logs/", temp_dir.path().display());
+
+            append_entry(Path::new(&dir), &json!({"wd": "/a"}), LogFormat::Json).unwrap();
+
+            assert_eq!(collect_lines(Path::new(&dir)), vec![r#"{"wd":"/a"}"#]);
+        }
+
+        #[test]
+        fn skips_hidden_and_foreign_files() {
+            let temp_dir = TempDir::new().unwrap();
+            append_entry(temp_dir.path(), &json!({"wd": "/a"}), LogFormat::Json).unwrap();
+            fs::write(temp_dir.path().join(".partial.json.tmp"), "{\"wd\":\"/tmp\"}\n").unwrap();
+            fs::write(temp_dir.path().join("notes.txt"), "hello\n").unwrap();
+
+            assert_eq!(collect_lines(temp_dir.path()), vec![r#"{"wd":"/a"}"#]);
+        }
+
+        #[test]
+        fn reads_empty_directory() {
+            let temp_dir = TempDir::new().unwrap();
+            assert!(collect_lines(temp_dir.path()).is_empty());
+        }
+    }
+
+    mod zstd_tests {
+        use super::*;
+
+        #[test]
+        fn parses_format_names() {
+            assert_eq!(LogFormat::parse(""), Ok(LogFormat::Json));
+            assert_eq!(LogFormat::parse("json"), Ok(LogFormat::Json));
+            assert_eq!(LogFormat::parse("zstd"), Ok(LogFormat::Zstd));
+            assert!(LogFormat::parse("gzip").is_err());
+        }
+
+        #[test]
+        fn writes_header_and_compressed_frames() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.zst");
+
+            append_entry(&log_path, &json!({"wd": "/a"}), LogFormat::Zstd).unwrap();
+            append_entry(&log_path, &json!({"wd": "/b"}), LogFormat::Zstd).unwrap();
+
+            let raw = fs::read(&log_path).unwrap();
+            assert_eq!(raw[..4], ZSTD_MAGIC);
+            let decoded = String::from_utf8(zstd::decode_all(raw.as_slice()).unwrap()).unwrap();
+            assert!(is_header(decoded.lines().next().unwrap().as_bytes()));
+            assert_eq!(collect_lines(&log_path), vec![r#"{"wd":"/a"}"#, r#"{"wd":"/b"}"#]);
+        }
+
+        #[test]
+        fn keeps_existing_encoding_of_a_file() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.txt");
+
+            append_entry(&log_path, &json!({"wd": "/a"}), LogFormat::Json).unwrap();
+            append_entry(&log_path, &json!({"wd": "/b"}), LogFormat::Zstd).unwrap();
+
+            assert_eq!(fs::read_to_string(&log_path).unwrap(), "{\"wd\":\"/a\"}\n{\"wd\":\"/b\"}\n");
+        }
+
+        #[test]
+        fn appends_zstd_to_existing_zstd_file_in_json_mode() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.zst");
+
+            append_entry(&log_path, &json!({"wd": "/a"}), LogFormat::Zstd).unwrap();
+            append_entry(&log_path, &json!({"wd": "/b"}), LogFormat::Json).unwrap();
+
+            assert_eq!(fs::read(&log_path).unwrap()[..4], ZSTD_MAGIC);
+            assert_eq!(collect_lines(&log_path), vec![r#"{"wd":"/a"}"#, r#"{"wd":"/b"}"#]);
+        }
+
+        #[test]
+        fn reads_mixed_shard_encodings() {
+            let temp_dir = TempDir::new().unwrap();
+            append_entry(temp_dir.path(), &json!({"n": 0}), LogFormat::Zstd).unwrap();
+            append_entry(temp_dir.path(), &json!({"n": 1}), LogFormat::Json).unwrap();
+            append_entry(temp_dir.path(), &json!({"n": 2}), LogFormat::Zstd).unwrap();
+
+            assert_eq!(collect_lines(temp_dir.path()), vec![r#"{"n":0}"#, r#"{"n":1}"#, r#"{"n":2}"#]);
+        }
+
+        /// Records as a build of many files in a few directories logs them, sharing the
+        /// compiler, include directories and defines.
+        fn build_entries() -> Vec<Value> {
+            let includes: Vec<String> = ["include", "src", "third_party/abseil", "third_party/protobuf/src", "build/gen"]
+                .iter()
+                .map(|dir| format!("-I/home/user/projects/service/{}", dir))
+                .collect();
+            (0..200)
+                .map(|i| {
+                    let file = format!("src/module{}/file{}.cc", i % 7, i);
+                    let mut args = vec!["-std=c++17".to_string(), "-O2".to_string(), "-g".to_string()];
+                    args.extend(includes.iter().cloned());
+                    args.extend(["-DNDEBUG", "-DUSE_SSL=1", "-Wall", "-Wextra", "-fPIC"].map(String::from));
+                    args.extend(["-c".to_string(), file.clone(), "-o".to_string(), format!("build/{}.o", file)]);
+                    json!({"v": 1, "wd": "/home/user/projects/service", "compiler": "/usr/bin/clang++", "args": args})
+                })
+                .collect()
+        }
+
+        fn plain_size(entries: &[Value]) -> usize {
+            entries.iter().map(|entry| to_line(entry).unwrap().len()).sum()
+        }
+
+        #[test]
+        fn compresses_build_log_against_first_record() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.zst");
+            let entries = build_entries();
+            for entry in &entries {
+                append_entry(&log_path, entry, LogFormat::Zstd).unwrap();
+            }
+
+            let compressed = fs::metadata(&log_path).unwrap().len() as usize;
+            assert!(compressed * 5 < plain_size(&entries), "{} of {} bytes", compressed, plain_size(&entries));
+            let expected: Vec<String> = entries.iter().map(Value::to_string).collect();
+            assert_eq!(collect_lines(&log_path), expected);
+        }
+
+        #[test]
+        fn compresses_shards_against_shared_dictionary() {
+            let temp_dir = TempDir::new().unwrap();
+            let entries = build_entries();
+            for entry in &entries {
+                append_entry(temp_dir.path(), entry, LogFormat::Zstd).unwrap();
+            }
+
+            let shards = list_shards(temp_dir.path()).unwrap();
+            let compressed: usize = shards.iter().map(|shard| fs::metadata(shard).unwrap().len() as usize).sum();
+            assert!(compressed * 5 < plain_size(&entries), "{} of {} bytes", compressed, plain_size(&entries));
+            assert_eq!(fs::read(temp_dir.path().join(DICTIONARY_FILE)).unwrap(), to_line(&entries[0]).unwrap());
+            let expected: Vec<String> = entries.iter().map(Value::to_string).collect();
+            assert_eq!(collect_lines(temp_dir.path()), expected);
+        }
+
+        #[test]
+        fn reads_version_1_logs_and_shards_without_dictionary() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.zst");
+            let header = json!({ HEADER_KEY: 1, "encoding": "zstd" });
+            let mut records = LogFormat::Zstd.encode(&header, None).unwrap();
+            records.extend(LogFormat::Zstd.encode(&json!({"n": 0}), None).unwrap());
+            fs::write(&log_path, records).unwrap();
+
+            // Version 1 logs keep compressing records on their own
+            append_entry(&log_path, &json!({"n": 1}), LogFormat::Zstd).unwrap();
+            assert_eq!(collect_lines(&log_path), vec![r#"{"n":0}"#, r#"{"n":1}"#]);
+
+            let shard_dir = temp_dir.path().join("shards");
+            fs::create_dir(&shard_dir).unwrap();
+            fs::write(shard_dir.join("0-old.json.zst"), LogFormat::Zstd.encode(&json!({"n": 0}), None).unwrap()).unwrap();
+            append_entry(&shard_dir, &json!({"n": 1}), LogFormat::Zstd).unwrap();
+            assert_eq!(collect_lines(&shard_dir), vec![r#"{"n":0}"#, r#"{"n":1}"#]);
+        }
+
+        #[test]
+        fn reads_log_with_only_first_record() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("cc_hook.zst");
+
+            append_entry(&log_path, &json!({"wd": "/a"}), LogFormat::Zstd).unwrap();
+
+            assert_eq!(collect_lines(&log_path), vec![r#"{"wd":"/a"}"#]);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+/// Maximum nesting depth for response files referencing other response files.
+const MAX_RSP_DEPTH: usize = 32;
+
+/// Split the contents of a response file into arguments using GCC's quoting rules.
+/// Arguments are separated by unquoted whitespace, single and double quotes group
+/// characters (including whitespace), and a backslash escapes the next character anywhere.
+/// Works on raw bytes so arguments that are not valid UTF-8 are preserved.
+pub fn parse_rsp(content: &[u8]) -> Vec<OsString> {
+    let mut args = Vec::new();
+    let mut current = Vec::new();
+    let mut in_arg = false;
+    let mut squote = false;
+    let mut dquote = false;
+    let mut bsquote = false;
+
+    for &c in content {
+        if bsquote {
+            bsquote = false;
+            current.push(c);
+            in_arg = true;
+        } else if c == b'\\' {
+            bsquote = true;
+            in_arg = true;
+        } else if squote {
+            if c == b'\'' {
+                squote = false;
+            } else {
+                current.push(c);
+            }
+        } else if dquote {
+            if c == b'"' {
+                dquote = false;
+            } else {
+                current.push(c);
+            }
+        } else if c.is_ascii_whitespace() || c == b'\x0b' {
+            if in_arg {
+                args.push(OsString::from_vec(std::mem::take(&mut current)));
+                in_arg = false;
+            }
+        } else if c == b'\'' {
+            squote = true;
+            in_arg = true;
+        } else if c == b'"' {
+            dquote = true;
+            in_arg = true;
+        } else {
+            current.push(c);
+            in_arg = true;
+        }
+    }
+
+    if in_arg {
+        args.push(OsString::from_vec(current));
+    }
+    args
+}
+
+/// Expand `@file` arguments with the contents of the referenced response files.
+/// Relative paths are resolved against `wd`. Response files may reference other
+/// response files; arguments whose file cannot be read are kept unchanged, as GCC does.
+pub fn expand_response_files<S: AsRef<OsStr>>(args: &[S], wd: &Path) -> Vec<OsString> {
+    let mut out = Vec::with_capacity(args.len());
+    expand_into(args, wd, 0, &mut out);
+    out
+}
+
+/// Check if any argument references a response file.
+pub fn has_response_files<S: AsRef<OsStr>>(args: &[S]) -> bool {
+    args.iter().any(|a| rsp_name(a.as_ref()).is_some())
+}
+
+/// Return the file name referenced by an `@file` argument.
+fn rsp_name(arg: &OsStr) -> Option<&OsStr> {
+    let name = arg.as_bytes().strip_prefix(b"@")?;
+    if name.is_empty() {
+        return None;
+    }
+    Some(OsStr::from_bytes(name))
+}
+
+fn expand_into<S: AsRef<OsStr>>(args: &[S], wd: &Path, depth: usize, out: &mut Vec<OsString>) {
+    for arg in args {
+        let arg = arg.as_ref();
+        let Some(name) = rsp_name(arg) else {
+            out.push(arg.to_os_string());
+            continue;
+        };
+        if depth >= MAX_RSP_DEPTH {
+            out.push(arg.to_os_string());
+            continue;
+        }
+        match fs::read(wd.join(name)) {
+            Ok(content) => expand_into(&parse_rsp(&content), wd, depth + 1, out),
+            Err(_) => out.push(arg.to_os_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    mod parse_rsp_tests {
+        use super::*;
+
+        #[test]
+        fn splits_on_whitespace() {
+            assert_eq!(parse_rsp(b"-c  main.c\n-o\tmain.o\n"), vec!["-c", "main.c", "-o", "main.o"]);
+        }
+
+        #[test]
+        fn handles_empty_content() {
+            assert!(parse_rsp(b"").is_empty());
+            assert!(parse_rsp(b" \n\t ").is_empty());
+        }
+
+        #[test]
+        fn double_quotes_group_whitespace() {
+            assert_eq!(parse_rsp(br#"-I"my dir" "a b.c""#), vec!["-Imy dir", "a b.c"]);
+        }
+
+        #[test]
+        fn single_quotes_group_whitespace() {
+            assert_eq!(parse_rsp(b"'-DNAME=a b' x.c"), vec!["-DNAME=a b", "x.c"]);
+        }
+
+        #[test]
+        fn quotes_of_other_kind_are_literal() {
+            assert_eq!(parse_rsp(br#"'say "hi"' "it's""#), vec![r#"say "hi""#, "it's"]);
+        }
+
+        #[test]
+        fn backslash_escapes_next_char() {
+            assert_eq!(parse_rsp(br"a\ b c\\d"), vec!["a b", r"c\d"]);
+        }
+
+        #[test]
+        fn backslash_escapes_inside_quotes() {
+            assert_eq!(parse_rsp(br#""-DV=\"1.0\"""#), vec![r#"-DV="1.0""#]);
+        }
+
+        #[test]
+        fn empty_quotes_produce_empty_argument() {
+            assert_eq!(parse_rsp(br#"a "" b"#), vec!["a", "", "b"]);
+        }
+
+        #[test]
+        fn preserves_non_utf8_bytes() {
+            let result = parse_rsp(b"-c caf\xe9.c");
+            assert_eq!(result[1].as_bytes(), b"caf\xe9.c");
+        }
+    }
+
+    mod expand_response_files_tests {
+        use super::*;
+
+        fn args(list: &[&str]) -> Vec<OsString> {
+            list.iter().map(OsString::from).collect()
+        }
+
+        #[test]
+        fn leaves_args_without_rsp_unchanged() {
+            let temp_dir = TempDir::new().unwrap();
+            let input = args(&["-c", "main.c"]);
+            assert_eq!(expand_response_files(&input, temp_dir.path()), input);
+        }
+
+        #[test]
+        fn expands_relative_rsp_file() {
+            let temp_dir = TempDir::new().unwrap();
+            fs::write(temp_dir.path().join("args.rsp"), "-O2 -c main.c").unwrap();
+            let result = expand_response_files(&args(&["-Wall", "@args.rsp", "-o", "main.o"]), temp_dir.path());
+            assert_eq!(result, args(&["-Wall", "-O2", "-c", "main.c", "-o", "main.o"]));
+        }
+
+        #[test]
+        fn expands_absolute_rsp_file() {
+            let temp_dir = TempDir::new().unwrap();
+            let rsp = temp_dir.path().join("args.rsp");
+            fs::write(&rsp, "-c main.c").unwrap();
+            let arg = OsString::from(format!("@{}", rsp.display()));
+            let result = expand_response_files(&[arg], Path::new("/nonexistent"));
+            assert_eq!(result, args(&["-c", "main.c"]));
+        }
+
+        #[test]
+        fn expands_nested_rsp_files() {
+            let temp_dir = TempDir::new().unwrap();
+            fs::write(temp_dir.path().join("outer.rsp"), "-c @inner.rsp").unwrap();
+            fs::write(temp_dir.path().join("inner.rsp"), "main.c -DX").unwrap();
+            let result = expand_response_files(&args(&["@outer.rsp"]), temp_dir.path());
+            assert_eq!(result, args(&["-c", "main.c", "-DX"]));
+        }
+
+        #[test]
+        fn keeps_missing_rsp_file_literal() {
+            let temp_dir = TempDir::new().unwrap();
+            let input = args(&["@missing.rsp", "-c", "main.c"]);
+            assert_eq!(expand_response_files(&input, temp_dir.path()), input);
+        }
+
+        #[test]
+        fn keeps_lone_at_sign() {
+            let temp_dir = TempDir::new().unwrap();
+            let input = args(&["@"]);
+            assert_eq!(expand_response_files(&input, temp_dir.path()), input);
+        }
+
+        #[test]
+        fn stops_on_recursive_rsp_file() {
+            let temp_dir = TempDir::new().unwrap();
+            fs::write(temp_dir.path().join("loop.rsp"), "-c @loop.rsp").unwrap();
+            let result = expand_response_files(&args(&["@loop.rsp"]), temp_dir.path());
+            assert_eq!(result.len(), MAX_RSP_DEPTH + 1);
+            assert_eq!(result.last().unwrap(), "@loop.rsp");
+        }
+    }
+
+    mod has_response_files_tests {
+        use super::*;
+
+        #[test]
+        fn detects_rsp_argument() {
+            assert!(has_response_files(&["-c", "@args.rsp"]));
+        }
+
+        #[test]
+        fn ignores_plain_arguments() {
+            assert!(!has_response_files(&["-c", "main.c", "@"]));
+        }
+    }
+}
@@ -1,11 +1,15 @@
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
 use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 use serde_json::json;
-use fs2::FileExt;
+use crate::encoding::{self, LogString};
+use crate::schema::LogEntry;
+use crate::{has_strict_env, log, rsp, LogFileError, ENV_COMPDB_ERROR_LOG, ENV_COMPDB_STRICT};
 
 /// Check if a command line represents a "configure" script execution.
 /// Returns true if the first argument (the executable) ends with "/configure" or is exactly "configure".
@@ -72,72 +76,145 @@ fn has_configure_parent() -> bool {
     }
 }
 
-pub fn run(log_file: &str, compiler: &str) {
-    let log_path = Path::new(&log_file);
-    if !log_path.is_absolute() {
-        eprintln!("Error: log file path must be absolute: {}", log_file);
-        std::process::exit(1);
+/// Run the wrapper: log the invocation, then replace this process with the compiler.
+/// Logging is fail-open: errors are reported and the compiler still runs with the
+/// original arguments, unless strict mode asks for the build to fail instead.
+pub fn run(log_file: Result<String, LogFileError>, compiler: &OsStr) {
+    // Get command line arguments (excluding the program name), which need not be valid UTF-8
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+
+    let logged = log_file
+        .map_err(|e| e.to_string())
+        .and_then(|log_file| log_invocation(Path::new(&log_file), compiler, &args));
+    if let Err(error) = logged {
+        report_error(&error, compiler, &args);
     }
 
-    // Get command line arguments (excluding the program name)
-    let args: Vec<String> = env::args().skip(1).collect();
+    // Execute the compiler with the provided arguments
+    let mut cmd = Command::new(compiler);
+    cmd.args(&args);
 
-    // Skip logging if we're running under a configure script
-    if !has_configure_parent() {
-        // Create lock file path next to the log file
-        let lock_file_path = log_path.with_extension("lock");
-
-        // Get current working directory
-        let wd = env::current_dir().expect("Failed to get current directory");
-        let wd_str = wd.to_string_lossy().to_string();
-
-        // Log the command execution
-        let log_entry = json!({
-            "wd": wd_str,
-            "compiler": compiler,
-            "args": args,
-        });
+    // Replace current process with the compiler
+    let error = cmd.exec();
 
-        // Create or open the lock file
-        let lock_file = File::create(&lock_file_path)
-            .expect("Failed to create lock file");
+    // If exec returns, it means there was an error
+    eprintln!("Failed to execute {}: {}", compiler.to_string_lossy(), error);
+    std::process::exit(1);
+}
 
-        // Acquire an exclusive lock
-        lock_file.lock_exclusive()
-            .expect("Failed to acquire lock");
+/// Append a log entry for this compiler invocation.
+fn log_invocation(log_path: &Path, compiler: &OsStr, args: &[OsString]) -> Result<(), String> {
+    // Skip logging if we're running under a configure script
+    if has_configure_parent() {
+        return Ok(());
+    }
 
-        // Open log file in append mode
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_file)
-            .expect("Failed to open log file");
+    // Get current working directory
+    let wd = env::current_dir()
+        .map_err(|e| format!("failed to get current directory: {}", e))?;
 
-        // Write log entry
-        writeln!(file, "{}", log_entry)
-            .expect("Failed to write to log file");
+    log_command(log_path, &wd, compiler, args)
+}
 
-        // Release the lock
-        lock_file.unlock()
-            .expect("Failed to release lock");
+/// Append a log entry for a compiler invocation to the log at `log_path`.
+/// This is what the wrapper does for every compiler call, so tools that run the
+/// compiler themselves can add their commands to the same log: response files are
+/// expanded, the entry is written in the current schema and in the encoding selected
+/// by COMPDB_LOG_FORMAT, and appended under the log's lock or as a new shard.
+pub fn log_command(log_path: &Path, wd: &Path, compiler: &OsStr, args: &[OsString]) -> Result<(), String> {
+    if !log_path.is_absolute() {
+        return Err(format!("log file path must be absolute: {}", log_path.display()));
     }
 
-    // Execute the compiler with the provided arguments
-    let mut cmd = Command::new(compiler);
-    cmd.args(&args);
+    // Response files are often deleted once the build step finishes, so log their contents
+    let logged_args = rsp::expand_response_files(args, wd);
 
-    // Replace current process with the compiler
-    let error = cmd.exec();
+    // Log the command execution
+    let log_entry = LogEntry::new(
+        wd.as_os_str().into(),
+        compiler.into(),
+        logged_args.into_iter().map(LogString).collect(),
+    );
 
-    // If exec returns, it means there was an error
-    eprintln!("Failed to execute {}: {}", compiler, error);
-    std::process::exit(1);
+    // Serialized as is, so the version field comes first where readers can sniff it
+    let format = log::LogFormat::from_env()?;
+    log::append_entry(log_path, &log_entry, format)
+}
+
+/// Report a logging failure on stderr and in the error log, if one is configured.
+/// Exits with an error in strict mode instead of letting the compiler run.
+fn report_error(error: &str, compiler: &OsStr, args: &[OsString]) {
+    eprintln!("compdb: failed to log compile command: {}", error);
+
+    if let Some(error_log) = env::var_os(ENV_COMPDB_ERROR_LOG) {
+        let entry = json!({
+            "time": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            "pid": std::process::id(),
+            "error": error,
+            "compiler": encoding::encode_os(compiler),
+            "args": encoding::encode_os_list(args),
+        });
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&error_log)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            eprintln!("compdb: failed to write error log {}: {}", Path::new(&error_log).display(), e);
+        }
+    }
+
+    if has_strict_env() {
+        eprintln!("compdb: {} is set, failing the build", ENV_COMPDB_STRICT);
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod log_command_tests {
+        use super::*;
+        use crate::schema;
+        use tempfile::TempDir;
+
+        #[test]
+        fn appends_entry_in_current_schema() {
+            let temp_dir = TempDir::new().unwrap();
+            let log_path = temp_dir.path().join("build.log");
+            let args = [OsString::from("-c"), OsString::from("main.c")];
+
+            log_command(&log_path, Path::new("/project"), OsStr::new("gcc"), &args).unwrap();
+            log_command(&log_path, Path::new("/project"), OsStr::new("g++"), &args).unwrap();
+
+            let lines: Vec<String> = log::read_lines(&log_path).unwrap().map(|l| String::from_utf8(l.unwrap()).unwrap()).collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].starts_with(r#"{"v":1,"wd":"/project","compiler":"gcc","args":"#), "{}", lines[0]);
+            let entry = schema::parse_entry(&lines[1]).unwrap();
+            assert_eq!(entry, LogEntry::new("/project".into(), "g++".into(), vec!["-c".into(), "main.c".into()]));
+        }
+
+        #[test]
+        fn expands_response_files_relative_to_directory() {
+            let temp_dir = TempDir::new().unwrap();
+            fs::write(temp_dir.path().join("args.rsp"), "-O2 main.c").unwrap();
+            let log_path = temp_dir.path().join("build.log");
+
+            log_command(&log_path, temp_dir.path(), OsStr::new("gcc"), &[OsString::from("@args.rsp")]).unwrap();
+
+            let line = String::from_utf8(log::read_lines(&log_path).unwrap().next().unwrap().unwrap()).unwrap();
+            let entry = schema::parse_entry(&line).unwrap();
+            assert_eq!(entry.args, vec!["-O2".into(), "main.c".into()]);
+        }
+
+        #[test]
+        fn rejects_relative_log_path() {
+            let err = log_command(Path::new("build.log"), Path::new("/p"), OsStr::new("gcc"), &[]).unwrap_err();
+            assert!(err.contains("absolute"));
+        }
+    }
+
     mod is_configure_command_tests {
         use super::*;
 
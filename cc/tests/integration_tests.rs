@@ -141,6 +141,154 @@ invalid json line
         assert_eq!(db.len(), 2);
     }
 
+    #[test]
+    fn generate_reads_mixed_schema_versions() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        let log_content = r#"{"wd":"/project","args":["-c","old.c"]}
+{"v":1,"wd":"/project","compiler":"clang","args":["-c","new.c"],"session":"abc"}
+{"v":99,"wd":"/project","compiler":"clang","args":["-c","future.c"]}"#;
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("unsupported log schema version 99"));
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(db[0]["arguments"][0], "/usr/bin/gcc");
+        assert_eq!(db[1]["arguments"][0], "clang");
+    }
+
+    #[test]
+    fn generate_replaces_existing_db_atomically() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        fs::write(&log_path, r#"{"wd":"/project","args":["-c","main.c"]}"#).unwrap();
+        let output_path = temp_dir.path().join("compile_commands.json");
+        fs::write(&output_path, "[]").unwrap();
+        fs::set_permissions(&output_path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success();
+
+        let db: Vec<Value> = serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+        assert_eq!(db.len(), 1);
+        assert_eq!(fs::metadata(&output_path).unwrap().permissions().mode() & 0o777, 0o600);
+        // Only the log, the database and its lock file remain, no temporary files
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+        assert!(temp_dir.path().join("compile_commands.json.lock").exists());
+    }
+
+    #[test]
+    fn generate_summarizes_skipped_lines_instead_of_printing_each() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        let log_content = r#"{"wd":"/project","args":["-c","main.c"]}
+{"wd":"/project","args":["-o","app","main.o"]}
+{"wd":"/project","args":["-o","test","test.o"]}
+not json"#;
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("warning: line 4: invalid entry"))
+            .stderr(predicate::str::contains("app").not())
+            .stderr(predicate::str::contains("Generated 1 entries from 4 log lines"))
+            .stderr(predicate::str::contains("2 non-compile invocations"));
+    }
+
+    #[test]
+    fn generate_verbose_prints_every_diagnostic() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        fs::write(&log_path, r#"{"wd":"/project","args":["-o","app","main.o"]}"#).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--verbose"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("warning: line 1: not a compile command: "))
+            .stderr(predicate::str::contains(r#""app""#));
+    }
+
+    #[test]
+    fn generate_quiet_prints_nothing() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        fs::write(&log_path, "{\"wd\":\"/project\",\"args\":[\"-c\",\"main.c\"]}\nnot json").unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--quiet"])
+            .assert()
+            .success()
+            .stderr(predicate::str::is_empty());
+    }
+
+    #[test]
+    fn generate_strict_fails_on_invalid_json_and_writes_report() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        fs::write(&log_path, "{\"wd\":\"/project\",\"args\":[\"-c\",\"main.c\"]}\nnot json").unwrap();
+        let report_path = temp_dir.path().join("report.json");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--strict", "--report"])
+            .arg(&report_path)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("1 malformed log lines"));
+
+        assert!(!temp_dir.path().join("compile_commands.json").exists());
+        let report: Value = serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+        assert_eq!(report["counts"]["invalid_entry"], 1);
+        let invalid = report["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|d| d["category"] == "invalid_entry")
+            .unwrap();
+        assert_eq!(invalid["line"], 2);
+    }
+
+    #[test]
+    fn generate_rejects_unknown_option() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        fs::write(&log_path, "").unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--frobnicate"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("unknown option for --generate: --frobnicate"));
+    }
+
     #[test]
     fn generate_handles_cpp_files() {
         let temp_dir = TempDir::new().unwrap();
@@ -224,7 +372,7 @@ invalid json line
             .arg("--generate")
             .assert()
             .success()
-            .stderr(predicate::str::contains("warning"));
+            .stderr(predicate::str::contains("1 non-compile invocations"));
 
         let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
         let db: Vec<Value> = serde_json::from_str(&content).unwrap();
@@ -885,3 +1033,528 @@ mod compdb_log_tests {
             .stderr(predicate::str::contains("COMPDB_LOG"));
     }
 }
+
+// ==================== Wrapper logging tests ====================
+
+mod wrapper_tests {
+    use super::*;
+
+    #[test]
+    fn logs_expanded_response_file_contents() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        fs::write(temp_dir.path().join("args.rsp"), "-O2 -c 'my file.c'").unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_CC", "true")
+            .arg("@args.rsp")
+            .assert()
+            .success();
+
+        // The response file is gone by the time the database is generated
+        fs::remove_file(temp_dir.path().join("args.rsp")).unwrap();
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let entry: Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!(entry["v"], 1);
+        assert_eq!(entry["args"], serde_json::json!(["-O2", "-c", "my file.c"]));
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db.len(), 1);
+        assert!(db[0]["file"].as_str().unwrap().ends_with("/my file.c"));
+    }
+
+    #[test]
+    fn runs_compiler_when_log_cannot_be_written() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("missing_dir").join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_CC", "true")
+            .env_remove("COMPDB_STRICT")
+            .args(["-c", "main.c"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("compdb: failed to log compile command"));
+    }
+
+    #[test]
+    fn runs_compiler_when_compdb_log_not_set() {
+        let temp_dir = TempDir::new().unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env_remove("COMPDB_LOG")
+            .env_remove("COMPDB_STRICT")
+            .env("COMPDB_CC", "true")
+            .args(["-c", "main.c"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("COMPDB_LOG"));
+    }
+
+    #[test]
+    fn preserves_compiler_exit_status_when_logging_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("missing_dir").join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_CC", "false")
+            .env_remove("COMPDB_STRICT")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("compdb: failed to log"))
+            .stderr(predicate::str::contains("failing the build").not());
+    }
+
+    #[test]
+    fn strict_mode_fails_when_log_cannot_be_written() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("missing_dir").join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_CC", "true")
+            .env("COMPDB_STRICT", "1")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("COMPDB_STRICT"));
+    }
+
+    #[test]
+    fn writes_logging_errors_to_error_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("missing_dir").join("cc_hook.txt");
+        let error_log = temp_dir.path().join("errors.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_ERROR_LOG", error_log.to_str().unwrap())
+            .env("COMPDB_CC", "true")
+            .env_remove("COMPDB_STRICT")
+            .args(["-c", "main.c"])
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(&error_log).unwrap();
+        let entry: Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert!(entry["error"].as_str().unwrap().contains("failed to"));
+        assert_eq!(entry["compiler"], "true");
+        assert_eq!(entry["args"], serde_json::json!(["-c", "main.c"]));
+    }
+}
+
+// ==================== Non-UTF-8 tests ====================
+
+mod non_utf8_tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn wrapper_logs_non_utf8_argument_as_hex() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_CC", "true")
+            .arg("-c")
+            .arg(OsStr::from_bytes(b"caf\xe9.c"))
+            .assert()
+            .success();
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let entry: Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!(entry["v"], 1);
+        assert_eq!(entry["args"], serde_json::json!(["-c", {"hex": "636166e92e63"}]));
+    }
+
+    #[test]
+    fn generate_reports_entries_that_cannot_be_represented() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        let log_content = r#"{"wd":"/project","args":["-c","main.c"]}
+{"wd":"/project","args":["-c",{"hex":"636166e92e63"}]}"#;
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("non-UTF-8"));
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db.len(), 1);
+        assert_eq!(db[0]["file"], "/project/main.c");
+    }
+}
+
+// ==================== Shard directory tests ====================
+
+mod shard_dir_tests {
+    use super::*;
+
+    #[test]
+    fn wrapper_writes_shards_and_generate_reads_them_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_dir = temp_dir.path().join("compdb-log");
+        fs::create_dir(&log_dir).unwrap();
+
+        for file in ["a.c", "b.c", "c.c"] {
+            cargo_bin_cmd!("compdb-cc")
+                .current_dir(temp_dir.path())
+                .env("COMPDB_LOG", log_dir.to_str().unwrap())
+                .env("COMPDB_CC", "true")
+                .args(["-c", file])
+                .assert()
+                .success();
+        }
+
+        assert_eq!(fs::read_dir(&log_dir).unwrap().count(), 3);
+        assert!(!temp_dir.path().join("compdb-log.lock").exists());
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_dir.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db.len(), 3);
+        assert!(db[0]["file"].as_str().unwrap().ends_with("/a.c"));
+        assert!(db[1]["file"].as_str().unwrap().ends_with("/b.c"));
+        assert!(db[2]["file"].as_str().unwrap().ends_with("/c.c"));
+    }
+
+    #[test]
+    fn single_file_logs_with_different_extensions_use_separate_locks() {
+        let temp_dir = TempDir::new().unwrap();
+
+        for name in ["build.log", "build.txt"] {
+            cargo_bin_cmd!("compdb-cc")
+                .current_dir(temp_dir.path())
+                .env("COMPDB_LOG", temp_dir.path().join(name).to_str().unwrap())
+                .env("COMPDB_CC", "true")
+                .args(["-c", "main.c"])
+                .assert()
+                .success();
+        }
+
+        assert!(temp_dir.path().join("build.log.lock").exists());
+        assert!(temp_dir.path().join("build.txt.lock").exists());
+    }
+}
+
+// ==================== Compressed log tests ====================
+
+mod zstd_log_tests {
+    use super::*;
+
+    #[test]
+    fn wrapper_writes_zstd_log_and_generate_reads_it() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.zst");
+
+        for file in ["a.c", "b.c"] {
+            cargo_bin_cmd!("compdb-cc")
+                .current_dir(temp_dir.path())
+                .env("COMPDB_LOG", log_path.to_str().unwrap())
+                .env("COMPDB_LOG_FORMAT", "zstd")
+                .env("COMPDB_CC", "true")
+                .args(["-c", file])
+                .assert()
+                .success();
+        }
+
+        assert!(fs::read_to_string(&log_path).is_err(), "log should not be plain text");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("warning").not());
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db.len(), 2);
+        assert!(db[0]["file"].as_str().unwrap().ends_with("/a.c"));
+        assert!(db[1]["file"].as_str().unwrap().ends_with("/b.c"));
+    }
+
+    #[test]
+    fn unknown_log_format_is_reported_but_compiler_runs() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .env("COMPDB_LOG_FORMAT", "gzip")
+            .env("COMPDB_CC", "true")
+            .env_remove("COMPDB_STRICT")
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("COMPDB_LOG_FORMAT"));
+    }
+}
+
+// ==================== append mode tests ====================
+
+mod append_tests {
+    use super::*;
+
+    #[test]
+    fn appends_entries_that_generate_picks_up() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--append", "--", "/usr/bin/clang", "-c", "gen.c", "-o", "gen.o"])
+            .assert()
+            .success();
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--append", "--directory", "/elsewhere", "--", "g++", "-c", "other.cpp"])
+            .assert()
+            .success();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .arg("--generate")
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db.len(), 2);
+        assert_eq!(db[0]["arguments"], serde_json::json!(["/usr/bin/clang", "-c", "gen.c", "-o", "gen.o"]));
+        assert_eq!(db[0]["file"], format!("{}/gen.c", temp_dir.path().display()));
+        assert_eq!(db[1]["directory"], "/elsewhere");
+        assert_eq!(db[1]["arguments"][0], "g++");
+    }
+
+    #[test]
+    fn does_not_run_the_compiler() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--append", "--", "false", "-c", "main.c"])
+            .assert()
+            .success();
+
+        assert_eq!(fs::read_to_string(&log_path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn writes_shards_and_compressed_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_dir = temp_dir.path().join("log");
+        fs::create_dir(&log_dir).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_dir.to_str().unwrap())
+            .env("COMPDB_LOG_FORMAT", "zstd")
+            .args(["--append", "--", "cc", "-c", "main.c"])
+            .assert()
+            .success();
+
+        // The first compressed shard is also published as the dictionary of later ones
+        let shards: Vec<_> = fs::read_dir(&log_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|path| !path.ends_with("zstd.dict"))
+            .collect();
+        assert_eq!(shards.len(), 1);
+        assert!(shards[0].to_str().unwrap().ends_with(".zst"));
+        assert!(log_dir.join("zstd.dict").exists());
+    }
+
+    #[test]
+    fn fails_without_log() {
+        cargo_bin_cmd!("compdb-cc")
+            .env_remove("COMPDB_LOG")
+            .args(["--append", "--", "gcc", "-c", "main.c"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("COMPDB_LOG environment variable is required"));
+    }
+
+    #[test]
+    fn fails_without_separator() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--append", "gcc", "-c", "main.c"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("unknown option for --append: gcc"));
+
+        assert!(!log_path.exists());
+    }
+}
+
+// ==================== path mapping tests ====================
+
+mod path_mapping_tests {
+    use super::*;
+
+    #[test]
+    fn generate_maps_container_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        let log_content = r#"{"v":1,"wd":"/build/src","compiler":"gcc","args":["-isystem","/build/src/third_party","--sysroot=/build/sysroot","-include","/build/src/config.h","-fprofile-use=/build/src/default.profdata","-c","main.c","-o","/build/src/main.o"]}"#;
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--map", "/build/src=/home/me/src", "--map", "/build=/opt/build"])
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db[0]["directory"], "/home/me/src");
+        assert_eq!(db[0]["file"], "/home/me/src/main.c");
+        assert_eq!(
+            db[0]["arguments"],
+            serde_json::json!([
+                "gcc", "-isystem", "/home/me/src/third_party", "--sysroot=/opt/build/sysroot", "-include",
+                "/home/me/src/config.h", "-fprofile-use=/home/me/src/default.profdata", "-c", "main.c", "-o",
+                "/home/me/src/main.o"
+            ])
+        );
+    }
+}
+
+// ==================== compiler built-ins tests ====================
+
+mod builtins_tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Write a fake GCC cross-compiler that answers the built-in queries.
+    fn fake_cross_gcc(dir: &std::path::Path) -> String {
+        let path = dir.join("aarch64-linux-gnu-gcc");
+        let script = "#!/bin/sh
+case \"$1\" in
+  -dumpmachine) echo aarch64-linux-gnu ;;
+  -dM) echo '#define __GNUC__ 12' ;;
+  *) printf '#include <...> search starts here:\\n /usr/aarch64-linux-gnu/include\\nEnd of search list.\\n' >&2 ;;
+esac
+";
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn generate_injects_target_and_system_includes() {
+        let temp_dir = TempDir::new().unwrap();
+        let compiler = fake_cross_gcc(temp_dir.path());
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        let wd = temp_dir.path().to_str().unwrap();
+        let log_content = format!(r#"{{"v":1,"wd":"{wd}","compiler":"{compiler}","args":["-c","main.c"]}}"#);
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--query-builtins"])
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(
+            db[0]["arguments"],
+            serde_json::json!([
+                compiler, "--target=aarch64-linux-gnu", "-nostdinc", "-isystem", "/usr/aarch64-linux-gnu/include",
+                "-c", "main.c"
+            ])
+        );
+        assert!(temp_dir.path().join("cc_hook.txt.builtins.json").exists());
+    }
+
+    #[test]
+    fn generate_keeps_entries_whose_compiler_cannot_be_queried() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        let log_content = r#"{"v":1,"wd":"/tmp","compiler":"/nonexistent/gcc","args":["-c","main.c"]}"#;
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--query-builtins"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("1 entries whose compiler could not be queried"));
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db[0]["arguments"], serde_json::json!(["/nonexistent/gcc", "-c", "main.c"]));
+    }
+}
+
+// ==================== GCC compatibility tests ====================
+
+mod gcc_compat_tests {
+    use super::*;
+
+    #[test]
+    fn generate_rewrites_gcc_flags() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("cc_hook.txt");
+        let log_content = r#"{"v":1,"wd":"/tmp","compiler":"gcc","args":["-fno-canonical-system-headers","-fplugin=x.so","-mfoo","-c","main.c"]}"#;
+        fs::write(&log_path, log_content).unwrap();
+
+        cargo_bin_cmd!("compdb-cc")
+            .current_dir(temp_dir.path())
+            .env("COMPDB_LOG", log_path.to_str().unwrap())
+            .args(["--generate", "--gcc-compat", "--compat-rule", "-mfoo => -DFOO"])
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(temp_dir.path().join("compile_commands.json")).unwrap();
+        let db: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(db[0]["arguments"], serde_json::json!(["gcc", "-DFOO", "-c", "main.c"]));
+    }
+}
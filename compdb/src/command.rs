@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::shell::{self, SplitError};
+
+/// An entry of a compilation database.
+/// The compiler invocation is given either as an argument list (`arguments`) or as a
+/// shell-quoted command line (`command`). Fields this model doesn't know are kept in
+/// `extra`, so tools can rewrite a database without losing information.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompileCommand {
+    /// Working directory of the compilation; relative paths are resolved against it.
+    pub directory: String,
+    /// Main source file of the compilation.
+    pub file: String,
+    /// Compiler invocation as a list of arguments, starting with the compiler.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<String>>,
+    /// Compiler invocation as a shell-quoted command line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Output file of the compilation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Fields not covered by this model, written back unchanged.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl CompileCommand {
+    /// Create an entry whose invocation is given as an argument list.
+    pub fn with_arguments(directory: impl Into<String>, file: impl Into<String>, arguments: Vec<String>) -> Self {
+        CompileCommand {
+            directory: directory.into(),
+            file: file.into(),
+            arguments: Some(arguments),
+            command: None,
+            output: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// Create an entry whose invocation is given as a shell-quoted command line.
+    pub fn with_command(directory: impl Into<String>, file: impl Into<String>, command: impl Into<String>) -> Self {
+        CompileCommand {
+            directory: directory.into(),
+            file: file.into(),
+            arguments: None,
+            command: Some(command.into()),
+            output: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// The compiler invocation as a list of arguments.
+    /// `arguments` takes precedence over `command` if an entry has both.
+    pub fn args(&self) -> Result<Vec<String>, SplitError> {
+        match (&self.arguments, &self.command) {
+            (Some(arguments), _) => Ok(arguments.clone()),
+            (None, Some(command)) => shell::split(command),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    /// The compiler invocation as a shell-quoted command line.
+    pub fn command_line(&self) -> String {
+        match (&self.arguments, &self.command) {
+            (Some(arguments), _) => shell::join(arguments),
+            (None, Some(command)) => command.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Replace the invocation with `args`, keeping the form the entry already uses.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        if self.arguments.is_none() && self.command.is_some() {
+            self.command = Some(shell::join(&args));
+        } else {
+            self.arguments = Some(args);
+            self.command = None;
+        }
+    }
+
+    /// Convert the entry to the `arguments` form.
+    pub fn to_arguments_form(&mut self) -> Result<(), SplitError> {
+        self.arguments = Some(self.args()?);
+        self.command = None;
+        Ok(())
+    }
+
+    /// Convert the entry to the `command` form.
+    pub fn to_command_form(&mut self) {
+        self.command = Some(self.command_line());
+        self.arguments = None;
+    }
+
+    /// Absolute path of the source file, resolved against the directory.
+    pub fn file_path(&self) -> PathBuf {
+        Path::new(&self.directory).join(&self.file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deserializes_command_form() {
+        let cmd: CompileCommand =
+            serde_json::from_str(r#"{"directory":"/build","command":"gcc -c 'my file.c'","file":"my file.c"}"#).unwrap();
+        assert_eq!(cmd.command.as_deref(), Some("gcc -c 'my file.c'"));
+        assert_eq!(cmd.args().unwrap(), vec!["gcc", "-c", "my file.c"]);
+    }
+
+    #[test]
+    fn deserializes_arguments_form_with_output() {
+        let cmd: CompileCommand = serde_json::from_str(
+            r#"{"directory":"/build","arguments":["gcc","-c","a.c","-o","a.o"],"file":"a.c","output":"a.o"}"#,
+        ).unwrap();
+        assert_eq!(cmd.arguments.as_ref().unwrap().len(), 5);
+        assert_eq!(cmd.output.as_deref(), Some("a.o"));
+        assert_eq!(cmd.command_line(), "gcc -c a.c -o a.o");
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let json = json!({"directory": "/b", "file": "a.c", "command": "cc a.c", "target": "x86_64", "n": 1});
+        let cmd: CompileCommand = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(cmd.extra["target"], "x86_64");
+        assert_eq!(serde_json::to_value(&cmd).unwrap(), json);
+    }
+
+    #[test]
+    fn omits_absent_fields() {
+        let cmd = CompileCommand::with_arguments("/b", "a.c", vec!["cc".to_string(), "a.c".to_string()]);
+        assert_eq!(
+            serde_json::to_string(&cmd).unwrap(),
+            r#"{"directory":"/b","file":"a.c","arguments":["cc","a.c"]}"#
+        );
+    }
+
+    #[test]
+    fn converts_between_forms() {
+        let mut cmd = CompileCommand::with_command("/b", "a b.c", r#"gcc -DX="1 2" -c 'a b.c'"#);
+        cmd.to_arguments_form().unwrap();
+        assert_eq!(cmd.arguments.as_ref().unwrap(), &vec!["gcc", "-DX=1 2", "-c", "a b.c"]);
+        assert!(cmd.command.is_none());
+
+        cmd.to_command_form();
+        assert_eq!(cmd.command.as_deref(), Some("gcc '-DX=1 2' -c 'a b.c'"));
+        assert!(cmd.arguments.is_none());
+        assert_eq!(cmd.args().unwrap(), vec!["gcc", "-DX=1 2", "-c", "a b.c"]);
+    }
+
+    #[test]
+    fn set_args_keeps_form() {
+        let mut cmd = CompileCommand::with_command("/b", "a.c", "gcc -c a.c");
+        cmd.set_args(vec!["clang".to_string(), "-c".to_string(), "a.c".to_string()]);
+        assert_eq!(cmd.command.as_deref(), Some("clang -c a.c"));
+
+        let mut cmd = CompileCommand::with_arguments("/b", "a.c", vec![]);
+        cmd.set_args(vec!["clang".to_string()]);
+        assert_eq!(cmd.arguments, Some(vec!["clang".to_string()]));
+    }
+
+    #[test]
+    fn resolves_file_path() {
+        assert_eq!(CompileCommand::with_command("/b", "src/a.c", "").file_path(), Path::new("/b/src/a.c"));
+        assert_eq!(CompileCommand::with_command("/b", "/abs/a.c", "").file_path(), Path::new("/abs/a.c"));
+    }
+}
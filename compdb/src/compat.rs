@@ -0,0 +1,241 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use crate::command::CompileCommand;
+use crate::flags;
+use crate::shell::{self, SplitError};
+
+/// GCC flags that clang rejects or misinterprets, in rule syntax (see `Rule`).
+const GCC_RULES: &[&str] = &[
+    // Code generation and tuning options clang doesn't know
+    "-fno-canonical-system-headers",
+    "-mno-fp-ret-in-387",
+    "-fconserve-stack",
+    "-fno-var-tracking-assignments",
+    "-fno-allow-store-data-races",
+    "-fsched-pressure",
+    "-fno-ipa-*",
+    "-mindirect-branch-register",
+    "-mrecord-mcount",
+    "-mskip-rax-setup",
+    "-mpreferred-stack-boundary=*",
+    "--param *",
+    "--param=*",
+    "-mindirect-branch=thunk-extern => -mretpoline-external-thunk",
+    // Prefix maps only affect output, and older clang versions reject them
+    "-fmacro-prefix-map=*",
+    "-ffile-prefix-map=*",
+    // GCC plugins can't be loaded by clang
+    "-fplugin=*",
+    "-fplugin-arg-*",
+    // Warnings only GCC has
+    "-Wmaybe-uninitialized",
+    "-Wno-maybe-uninitialized",
+    "-Wstringop-*",
+    "-Wno-stringop-*",
+    "-Wpacked-not-aligned",
+    "-Wno-packed-not-aligned",
+    "-Wimplicit-fallthrough=* => -Wimplicit-fallthrough",
+];
+
+/// How a rule matches a compiler argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Exact(String),
+    Prefix(String),
+}
+
+impl Pattern {
+    pub fn matches(&self, arg: &str) -> bool {
+        match self {
+            Pattern::Exact(flag) => arg == flag,
+            Pattern::Prefix(prefix) => arg.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// A flag rewriting rule, written as `FLAG[ *][ => REPLACEMENT]`.
+/// A trailing `*` on the flag matches any flag with that prefix; a separate ` *` also
+/// matches the value following the flag. Matching arguments are replaced by the
+/// shell-quoted `REPLACEMENT`, or removed if there is none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub pattern: Pattern,
+    /// The flag is followed by a value argument, which is replaced with it.
+    pub with_value: bool,
+    pub replacement: Vec<String>,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid flag rule {:?}, expected FLAG[ *][ => REPLACEMENT]", s);
+        let (pattern, replacement) = match s.split_once("=>") {
+            Some((pattern, replacement)) => (pattern.trim(), shell::split(replacement).map_err(|_| invalid())?),
+            None => (s.trim(), Vec::new()),
+        };
+        let (pattern, with_value) = match pattern.strip_suffix(" *") {
+            Some(flag) => (flag.trim_end(), true),
+            None => (pattern, false),
+        };
+        if !pattern.starts_with('-') || pattern.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let pattern = match pattern.strip_suffix('*') {
+            Some(prefix) => Pattern::Prefix(prefix.to_string()),
+            None => Pattern::Exact(pattern.to_string()),
+        };
+        Ok(Rule { pattern, with_value, replacement })
+    }
+}
+
+/// Read rules from a file with one rule per line. Blank lines and lines starting with
+/// `#` are ignored.
+pub fn load_rules(path: &Path) -> Result<Vec<Rule>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| line.parse().map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e)))
+        .collect()
+}
+
+/// Rewrites the flags of compile commands so clang-based tools accept them.
+/// The first matching rule wins. GCC precompiled headers given with `-include` are
+/// replaced by the header they were built from.
+#[derive(Debug, Clone, Default)]
+pub struct Compat {
+    pub rules: Vec<Rule>,
+}
+
+impl Compat {
+    /// The built-in GCC table, preceded by `rules` so they can override it.
+    pub fn gcc_with(rules: Vec<Rule>) -> Self {
+        let mut all = rules;
+        all.extend(GCC_RULES.iter().map(|rule| rule.parse::<Rule>().expect("built-in rule is valid")));
+        Compat { rules: all }
+    }
+
+    /// Rewrite a compiler argument list, starting with the compiler.
+    pub fn rewrite_args(&self, args: &[String]) -> Vec<String> {
+        let Some((compiler, rest)) = args.split_first() else {
+            return Vec::new();
+        };
+        let mut out = vec![compiler.clone()];
+        let mut args = rest.iter();
+        while let Some(arg) = args.next() {
+            if let Some(rule) = self.rules.iter().find(|rule| rule.pattern.matches(arg)) {
+                if rule.with_value {
+                    args.next();
+                }
+                out.extend(rule.replacement.iter().cloned());
+            } else if flags::takes_value(arg) {
+                out.push(arg.clone());
+                if let Some(value) = args.next() {
+                    let value = match value.strip_suffix(".gch") {
+                        Some(header) if arg == "-include" => header,
+                        _ => value,
+                    };
+                    out.push(value.to_string());
+                }
+            } else {
+                out.push(arg.clone());
+            }
+        }
+        out
+    }
+
+    /// Rewrite the flags of a compile command in place, keeping its `command` or `arguments` form.
+    pub fn rewrite(&self, cmd: &mut CompileCommand) -> Result<(), SplitError> {
+        let args = cmd.args()?;
+        cmd.set_args(self.rewrite_args(&args));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule: Rule = "-fplugin=*".parse().unwrap();
+        assert_eq!(rule, Rule { pattern: Pattern::Prefix("-fplugin=".to_string()), with_value: false, replacement: vec![] });
+
+        let rule: Rule = "--param * => -O2 '-DX=a b'".parse().unwrap();
+        assert_eq!(rule.pattern, Pattern::Exact("--param".to_string()));
+        assert!(rule.with_value);
+        assert_eq!(rule.replacement, args(&["-O2", "-DX=a b"]));
+
+        assert!("fplugin".parse::<Rule>().is_err());
+        assert!("-a -b".parse::<Rule>().is_err());
+        assert!(" => -O2".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn built_in_table_is_valid() {
+        assert!(!Compat::gcc_with(Vec::new()).rules.is_empty());
+    }
+
+    #[test]
+    fn removes_and_translates_gcc_flags() {
+        let compat = Compat::gcc_with(Vec::new());
+        let rewritten = compat.rewrite_args(&args(&[
+            "gcc", "-fno-canonical-system-headers", "-mno-fp-ret-in-387", "-fconserve-stack", "-Wno-maybe-uninitialized",
+            "-fmacro-prefix-map=/build=.", "-fplugin=./gcc-plugin.so", "-fplugin-arg-gcc-plugin-x=1", "--param",
+            "max-inline-insns=5", "-mindirect-branch=thunk-extern", "-Wimplicit-fallthrough=3", "-O2", "-c", "a.c",
+        ]));
+        assert_eq!(
+            rewritten,
+            args(&["gcc", "-mretpoline-external-thunk", "-Wimplicit-fallthrough", "-O2", "-c", "a.c"])
+        );
+    }
+
+    #[test]
+    fn replaces_precompiled_header_and_skips_option_values() {
+        let compat = Compat::gcc_with(Vec::new());
+        let rewritten = compat.rewrite_args(&args(&[
+            "g++", "-include", "pch/all.h.gch", "-o", "-fconserve-stack", "-Xclang", "-fplugin=x", "-c", "a.cpp",
+        ]));
+        assert_eq!(
+            rewritten,
+            args(&["g++", "-include", "pch/all.h", "-o", "-fconserve-stack", "-Xclang", "-fplugin=x", "-c", "a.cpp"])
+        );
+    }
+
+    #[test]
+    fn user_rules_take_precedence() {
+        let compat = Compat::gcc_with(vec!["-fconserve-stack => -DCONSERVE".parse().unwrap(), "-march=*".parse().unwrap()]);
+        assert_eq!(
+            compat.rewrite_args(&args(&["gcc", "-fconserve-stack", "-march=armv8-a", "-c", "a.c"])),
+            args(&["gcc", "-DCONSERVE", "-c", "a.c"])
+        );
+    }
+
+    #[test]
+    fn rewrites_command_form() {
+        let mut cmd = CompileCommand::with_command("/b", "a.c", "gcc -fconserve-stack -c a.c");
+        Compat::gcc_with(Vec::new()).rewrite(&mut cmd).unwrap();
+        assert_eq!(cmd.command.as_deref(), Some("gcc -c a.c"));
+    }
+
+    #[test]
+    fn loads_rules_from_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("rules");
+        fs::write(&path, "# local rules\n\n-mfoo\n  -Wbar=* => -Wbar\n").unwrap();
+        assert_eq!(load_rules(&path).unwrap().len(), 2);
+
+        fs::write(&path, "-mfoo\nbogus\n").unwrap();
+        let err = load_rules(&path).unwrap_err();
+        assert!(err.contains("rules:2"));
+    }
+}
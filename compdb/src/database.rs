@@ -0,0 +1,121 @@
+use std::io::Write;
+use std::path::Path;
+use crate::command::CompileCommand;
+use crate::output;
+
+/// Parse a compilation database from its JSON text.
+/// Every entry must give its invocation as `arguments` or `command`.
+pub fn parse(content: &str) -> Result<Vec<CompileCommand>, Box<dyn std::error::Error>> {
+    let commands: Vec<CompileCommand> = serde_json::from_str(content)?;
+    if let Some((i, cmd)) = commands.iter().enumerate().find(|(_, c)| c.arguments.is_none() && c.command.is_none()) {
+        return Err(format!("entry {} for {} has neither \"arguments\" nor \"command\"", i, cmd.file).into());
+    }
+    Ok(commands)
+}
+
+/// Load a compilation database from a file.
+pub fn load(path: &Path) -> Result<Vec<CompileCommand>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    parse(&content).map_err(|e| format!("invalid compilation database {}: {}", path.display(), e).into())
+}
+
+/// Save a compilation database as pretty-printed JSON, replacing `path` atomically.
+pub fn save(path: &Path, commands: &[CompileCommand]) -> Result<(), Box<dyn std::error::Error>> {
+    output::write_atomic(path, |out| {
+        let mut writer = Writer::new(out);
+        for cmd in commands {
+            writer.write(cmd)?;
+        }
+        writer.finish()?;
+        Ok(())
+    })
+}
+
+/// Writes compilation database entries as a pretty-printed JSON array, one at a time,
+/// so a database never has to be held in memory as a whole.
+pub struct Writer<W: Write> {
+    out: W,
+    count: usize,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Self {
+        Writer { out, count: 0 }
+    }
+
+    /// Append an entry to the array.
+    pub fn write(&mut self, cmd: &CompileCommand) -> Result<(), Box<dyn std::error::Error>> {
+        self.out.write_all(if self.count == 0 { b"[\n" } else { b",\n" })?;
+        let pretty = serde_json::to_string_pretty(cmd)?;
+        for (i, line) in pretty.lines().enumerate() {
+            if i > 0 {
+                self.out.write_all(b"\n")?;
+            }
+            write!(self.out, "  {}", line)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Close the array and flush the output.
+    pub fn finish(mut self) -> Result<W, Box<dyn std::error::Error>> {
+        self.out.write_all(if self.count == 0 { b"[]" } else { b"\n]" })?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn sample() -> Vec<CompileCommand> {
+        vec![
+            CompileCommand::with_command("/b", "a.c", "gcc -c a.c"),
+            CompileCommand::with_arguments("/b", "b.c", vec!["gcc".to_string(), "-c".to_string(), "b.c".to_string()]),
+        ]
+    }
+
+    #[test]
+    fn writer_output_matches_pretty_printing() {
+        let out = Writer::new(Vec::new());
+        assert_eq!(out.finish().unwrap(), b"[]");
+
+        let mut writer = Writer::new(Vec::new());
+        for cmd in sample() {
+            writer.write(&cmd).unwrap();
+        }
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(out, serde_json::to_string_pretty(&sample()).unwrap());
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+
+        save(&path, &sample()).unwrap();
+
+        assert_eq!(load(&path).unwrap(), sample());
+    }
+
+    #[test]
+    fn rejects_entry_without_invocation() {
+        let err = parse(r#"[{"directory":"/b","file":"a.c"}]"#).unwrap_err();
+        assert!(err.to_string().contains("neither"));
+    }
+
+    #[test]
+    fn load_reports_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+        fs::write(&path, "{").unwrap();
+
+        let err = load(&path).unwrap_err().to_string();
+        assert!(err.contains("invalid compilation database"));
+        assert!(err.contains("compile_commands.json"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use crate::command::CompileCommand;
+
+/// Find the Makefile dependency file a compile command writes: the `-MF` value, the
+/// file given to `-Wp,-MD,` as in Linux kernel builds, or the output with a `.d`
+/// extension for `-MD` and `-MMD`. Relative paths are resolved against the directory.
+pub fn path(cmd: &CompileCommand) -> Option<PathBuf> {
+    let args = cmd.args().ok()?;
+    let mut depfile = None;
+    let mut output = cmd.output.clone();
+    let mut writes_depfile = false;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-MF" => depfile = args.next().cloned(),
+            "-o" => output = args.next().cloned(),
+            "-MD" | "-MMD" => writes_depfile = true,
+            _ => {
+                if let Some(file) = arg.strip_prefix("-Wp,-MD,").or_else(|| arg.strip_prefix("-Wp,-MMD,")) {
+                    depfile = Some(file.to_string());
+                } else if let Some(file) = arg.strip_prefix("-MF") {
+                    depfile = Some(file.to_string());
+                } else if let Some(file) = arg.strip_prefix("-o") {
+                    output = Some(file.to_string());
+                }
+            }
+        }
+    }
+    let depfile = match depfile {
+        Some(depfile) => PathBuf::from(depfile),
+        None if writes_depfile => Path::new(&output?).with_extension("d"),
+        None => return None,
+    };
+    Some(Path::new(&cmd.directory).join(depfile))
+}
+
+/// Parse the prerequisites of all rules in a Makefile dependency file, as written by
+/// `-MD`: escaped spaces, `$$` and line continuations are handled.
+pub fn parse(content: &str) -> Vec<String> {
+    let content = content.replace("\\\r\n", " ").replace("\\\n", " ");
+    let mut prerequisites = Vec::new();
+    for line in content.lines() {
+        let mut words = split_words(line).into_iter();
+        // Skip the targets, up to the word ending with the rule's colon
+        let mut found_colon = false;
+        for word in words.by_ref() {
+            if word == ":" {
+                found_colon = true;
+                break;
+            }
+            if let Some(target) = word.strip_suffix(':') {
+                found_colon = !target.is_empty();
+                break;
+            }
+        }
+        if found_colon {
+            prerequisites.extend(words);
+        }
+    }
+    prerequisites
+}
+
+/// Split a dependency line into words at unescaped whitespace.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ' | '#')) => word.extend(chars.next()),
+            '$' if chars.peek() == Some(&'$') => word.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_depfile_of_command() {
+        let path_of = |command: &str| path(&CompileCommand::with_command("/b", "a.c", command));
+        assert_eq!(path_of("gcc -MD -MF deps/a.d -c a.c -o a.o"), Some(PathBuf::from("/b/deps/a.d")));
+        assert_eq!(path_of("gcc -MMD -c a.c -o obj/a.o"), Some(PathBuf::from("/b/obj/a.d")));
+        assert_eq!(
+            path_of("gcc -Wp,-MMD,kernel/.fork.o.d -c -o kernel/fork.o kernel/fork.c"),
+            Some(PathBuf::from("/b/kernel/.fork.o.d"))
+        );
+        assert_eq!(path_of("gcc -c a.c -o a.o"), None);
+        assert_eq!(path_of("gcc -MD -c a.c"), None);
+    }
+
+    #[test]
+    fn parses_prerequisites() {
+        let content = "obj/a.o: src/a.c include/a.h \\\n  /usr/include/stdio.h include/with\\ space.h\n\ninclude/a.h:\n";
+        assert_eq!(parse(content), vec!["src/a.c", "include/a.h", "/usr/include/stdio.h", "include/with space.h"]);
+        assert_eq!(parse("a.o : a.c b$$.h\n"), vec!["a.c", "b$.h"]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+
+/// Key of the JSON object carrying a value that is not valid UTF-8.
+/// Such values are logged as `{"hex": "<lowercase hex of the raw bytes>"}`.
+pub const HEX_KEY: &str = "hex";
+
+/// Encode an OS string for the log.
+/// Valid UTF-8 is written as a plain JSON string, anything else as a hex object.
+pub fn encode_os(s: &OsStr) -> Value {
+    match s.to_str() {
+        Some(s) => Value::String(s.to_string()),
+        None => json!({ HEX_KEY: to_hex(s.as_bytes()) }),
+    }
+}
+
+/// Encode a list of OS strings for the log.
+pub fn encode_os_list(list: &[OsString]) -> Value {
+    Value::Array(list.iter().map(|s| encode_os(s)).collect())
+}
+
+/// Decode a value written by `encode_os` back into the original bytes.
+/// Returns None for values that are neither a string nor a valid hex object.
+pub fn decode_os(value: &Value) -> Option<OsString> {
+    if let Some(s) = value.as_str() {
+        return Some(OsString::from(s));
+    }
+    let hex = value.get(HEX_KEY)?.as_str()?;
+    from_hex(hex).map(OsString::from_vec)
+}
+
+/// An OS string stored in the log with the encoding of `encode_os`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LogString(pub OsString);
+
+impl From<&str> for LogString {
+    fn from(s: &str) -> Self {
+        LogString(OsString::from(s))
+    }
+}
+
+impl From<&OsStr> for LogString {
+    fn from(s: &OsStr) -> Self {
+        LogString(s.to_os_string())
+    }
+}
+
+impl Serialize for LogString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        encode_os(&self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LogString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        decode_os(&value)
+            .map(LogString)
+            .ok_or_else(|| de::Error::custom("expected a string or a hex object"))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_utf8_as_plain_string() {
+        assert_eq!(encode_os(OsStr::new("main.c")), json!("main.c"));
+        assert_eq!(encode_os(OsStr::new("файл.c")), json!("файл.c"));
+    }
+
+    #[test]
+    fn encodes_invalid_utf8_as_hex() {
+        let s = OsStr::from_bytes(b"caf\xe9.c");
+        assert_eq!(encode_os(s), json!({"hex": "636166e92e63"}));
+    }
+
+    #[test]
+    fn roundtrips_invalid_utf8() {
+        let s = OsStr::from_bytes(b"/src/\xff\xfe/x.c");
+        assert_eq!(decode_os(&encode_os(s)).unwrap(), s);
+    }
+
+    #[test]
+    fn encodes_list() {
+        let list = vec![OsString::from("-c"), OsString::from_vec(b"\x80.c".to_vec())];
+        assert_eq!(encode_os_list(&list), json!(["-c", {"hex": "802e63"}]));
+    }
+
+    #[test]
+    fn decodes_plain_string() {
+        assert_eq!(decode_os(&json!("-O2")).unwrap(), "-O2");
+    }
+
+    #[test]
+    fn log_string_roundtrips_through_serde() {
+        let s = LogString(OsString::from_vec(b"a\xffb".to_vec()));
+        let json = serde_json::to_string(&s).unwrap();
+        assert_eq!(json, r#"{"hex":"61ff62"}"#);
+        assert_eq!(serde_json::from_str::<LogString>(&json).unwrap(), s);
+        assert_eq!(serde_json::to_string(&LogString::from("main.c")).unwrap(), r#""main.c""#);
+    }
+
+    #[test]
+    fn log_string_rejects_non_string_values() {
+        assert!(serde_json::from_str::<LogString>("123").is_err());
+        assert!(serde_json::from_str::<LogString>("null").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_values() {
+        assert!(decode_os(&json!(123)).is_none());
+        assert!(decode_os(&json!(null)).is_none());
+        assert!(decode_os(&json!({"hex": "abc"})).is_none());
+        assert!(decode_os(&json!({"hex": "zz"})).is_none());
+        assert!(decode_os(&json!({"other": "00"})).is_none());
+    }
+}
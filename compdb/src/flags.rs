@@ -0,0 +1,27 @@
+/// Compiler options that take their value as the next argument when it isn't joined,
+/// e.g. `-o out.o` or `-Xclang -ast-dump`.
+const SEPARATE_VALUE_OPTIONS: &[&str] = &[
+    "-o", "-I", "-D", "-U", "-x", "-L", "-l", "-include", "-imacros", "-include-pch", "-isystem", "-isysroot",
+    "-idirafter", "-iquote", "-iprefix", "-iwithprefix", "-iwithprefixbefore", "--sysroot", "-MF", "-MT", "-MQ",
+    "-Xclang", "-Xlinker", "-Xassembler", "-Xpreprocessor", "-arch", "-target", "--param", "-aux-info", "-T",
+];
+
+/// Check if `arg` is an option whose value is the following argument.
+pub fn takes_value(arg: &str) -> bool {
+    SEPARATE_VALUE_OPTIONS.contains(&arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_separate_value_options() {
+        assert!(takes_value("-o"));
+        assert!(takes_value("-Xclang"));
+        assert!(takes_value("--param"));
+        assert!(!takes_value("-o2"));
+        assert!(!takes_value("-Iinclude"));
+        assert!(!takes_value("-c"));
+    }
+}
@@ -0,0 +1,26 @@
+//! Compilation database model shared by the compdb tools.
+//!
+//! - [`CompileCommand`] is a typed entry of `compile_commands.json`.
+//! - [`database`] loads, saves and streams whole databases.
+//! - [`shell`] converts between the `command` and `arguments` forms of an entry.
+//! - [`flags`] knows which compiler options take a separate value.
+//! - [`compat`] rewrites GCC flags that clang-based tools reject.
+//! - [`depfile`] finds and parses the Makefile dependency files of entries.
+//! - [`paths`] normalizes and remaps the paths of entries.
+//! - [`portable`] exports databases independent of the checkout location and instantiates them again.
+//! - [`schema`] and [`encoding`] define the records logged by the compiler wrappers.
+//! - [`output`] replaces files atomically under an advisory lock.
+
+pub mod command;
+pub mod compat;
+pub mod database;
+pub mod depfile;
+pub mod encoding;
+pub mod flags;
+pub mod output;
+pub mod paths;
+pub mod portable;
+pub mod schema;
+pub mod shell;
+
+pub use command::CompileCommand;
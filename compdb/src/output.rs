@@ -0,0 +1,181 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use fs2::FileExt;
+
+/// Path of the lock file guarding an output file, e.g. `compile_commands.json.lock`.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_os_string();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Take an advisory lock on the output file at `path`, using `<path>.lock`.
+/// The lock is held until the returned file is dropped, so concurrent runs of
+/// generate and filter on the same database don't interleave.
+pub fn lock(path: &Path) -> Result<File, String> {
+    let lock_file_path = lock_path(path);
+    let lock_file = File::create(&lock_file_path)
+        .map_err(|e| format!("failed to create lock file {}: {}", lock_file_path.display(), e))?;
+    lock_file.lock_exclusive()
+        .map_err(|e| format!("failed to acquire lock {}: {}", lock_file_path.display(), e))?;
+    Ok(lock_file)
+}
+
+/// Replace the file at `path` with the content produced by `write`.
+/// The content goes to a temporary file in the same directory, which is synced and
+/// renamed over the target, so readers see either the old or the new file, never a
+/// partial one. The permissions of an existing target are kept. If `path` is a symbolic
+/// link, the file it points to is replaced and the link is kept.
+pub fn write_atomic<T>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let path = &resolve_link(path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name()
+        .ok_or_else(|| format!("invalid output path: {}", path.display()))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = dir.join(tmp_name);
+
+    let result = write_tmp(path, dir, &tmp_path, write);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Resolve `path` to the file it points to if it is a symbolic link.
+fn resolve_link(path: &Path) -> Result<PathBuf, String> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).map_err(|e| format!("failed to resolve {}: {}", path.display(), e))
+        }
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+fn write_tmp<T>(
+    path: &Path,
+    dir: &Path,
+    tmp_path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(tmp_path)
+        .map_err(|e| format!("failed to create {}: {}", tmp_path.display(), e))?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())
+            .map_err(|e| format!("failed to set permissions of {}: {}", tmp_path.display(), e))?;
+    }
+
+    let mut out = BufWriter::new(file);
+    let value = write(&mut out)?;
+    out.flush()
+        .and_then(|_| out.get_ref().sync_all())
+        .map_err(|e| format!("failed to write {}: {}", tmp_path.display(), e))?;
+    drop(out);
+
+    fs::rename(tmp_path, path)
+        .map_err(|e| format!("failed to replace {}: {}", path.display(), e))?;
+    // Make the rename itself durable; not every filesystem supports syncing a directory
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn write_str(path: &Path, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+        write_atomic(path, |out| Ok(out.write_all(content.as_bytes())?))
+    }
+
+    #[test]
+    fn creates_new_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+
+        write_str(&path, "[]").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]");
+    }
+
+    #[test]
+    fn replaces_existing_file_without_leftovers() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+        fs::write(&path, "old").unwrap();
+
+        write_str(&path, "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn keeps_old_content_when_writing_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+        fs::write(&path, "old").unwrap();
+
+        let result = write_atomic(&path, |out| {
+            out.write_all(b"partial")?;
+            Err::<(), _>("interrupted".into())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn preserves_permissions() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_str(&path, "new").unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn replaces_target_of_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("build")).unwrap();
+        let target = temp_dir.path().join("build/compile_commands.json");
+        let path = temp_dir.path().join("compile_commands.json");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink("build/compile_commands.json", &path).unwrap();
+
+        write_str(&path, "new").unwrap();
+
+        assert!(fs::symlink_metadata(&path).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_dir(temp_dir.path().join("build")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn lock_uses_lock_suffix() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("compile_commands.json");
+
+        let _lock = lock(&path).unwrap();
+
+        assert!(temp_dir.path().join("compile_commands.json.lock").exists());
+    }
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use crate::command::CompileCommand;
+use crate::shell::SplitError;
+
+/// Options followed by a path as the next argument. Single-letter ones and those in
+/// `JOINED_PATH_OPTIONS` may also be joined with it (`-Iinclude`).
+const PATH_OPTIONS: &[&str] = &[
+    "-isystem", "-isysroot", "-idirafter", "-iquote", "-imacros", "-include", "--sysroot", "-MF", "-I", "-L", "-o",
+];
+
+/// Multi-letter path options that also accept their path joined. Others, such as
+/// `-include`, are prefixes of unrelated options like `-include-pch`.
+const JOINED_PATH_OPTIONS: &[&str] = &["-isystem", "-isysroot", "-idirafter", "-iquote", "-MF"];
+
+/// Options whose path is joined with `=`.
+const PATH_EQ_OPTIONS: &[&str] = &[
+    "--sysroot=", "-fprofile-use=", "-fprofile-instr-use=", "-fprofile-generate=", "-fprofile-instr-generate=",
+    "-fsanitize-ignorelist=", "-fsanitize-blacklist=",
+];
+
+/// A path prefix mapping given as `FROM=TO`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl FromStr for PathMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() => Ok(PathMap { from: normalize(Path::new(from)), to: PathBuf::from(to) }),
+            _ => Err(format!("invalid path mapping {:?}, expected FROM=TO", s)),
+        }
+    }
+}
+
+impl fmt::Display for PathMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.from.display(), self.to.display())
+    }
+}
+
+/// Normalize a path lexically: drop `.` components and resolve `..` against the
+/// preceding component. Symbolic links are not consulted.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // `/..` is `/`
+                Some(Component::RootDir) => {}
+                _ => out.push(".."),
+            },
+            c => out.push(c),
+        }
+    }
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+/// Canonicalize a path: resolve symbolic links if it exists, otherwise normalize it lexically.
+pub fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| normalize(path))
+}
+
+/// Apply the first mapping whose prefix matches `path`, comparing whole components.
+pub fn map_path(path: &Path, maps: &[PathMap]) -> Option<PathBuf> {
+    maps.iter().find_map(|map| {
+        let rest = path.strip_prefix(&map.from).ok()?;
+        Some(if rest.as_os_str().is_empty() { map.to.clone() } else { map.to.join(rest) })
+    })
+}
+
+/// Apply `f` to every path in compiler arguments that don't include the compiler:
+/// the values of path options and absolute input files.
+pub fn map_path_args(args: &[String], mut f: impl FnMut(&str) -> String) -> Vec<String> {
+    let mut out = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(option) = PATH_OPTIONS.iter().find(|&&o| arg == o) {
+            out.push(option.to_string());
+            if let Some(value) = args.next() {
+                out.push(f(value));
+            }
+        } else if let Some(option) = PATH_EQ_OPTIONS.iter().find(|&&o| arg.starts_with(o) && arg.len() > o.len()) {
+            out.push(format!("{}{}", option, f(&arg[option.len()..])));
+        } else if let Some(option) = joined_path_option(arg) {
+            out.push(format!("{}{}", option, f(&arg[option.len()..])));
+        } else if !arg.starts_with('-') && Path::new(arg).is_absolute() {
+            out.push(f(arg));
+        } else {
+            out.push(arg.clone());
+        }
+    }
+    out
+}
+
+/// Find the path option an argument starts with in joined form, preferring the longest.
+fn joined_path_option(arg: &str) -> Option<&'static str> {
+    PATH_OPTIONS
+        .iter()
+        .copied()
+        .filter(|o| (o.len() == 2 || JOINED_PATH_OPTIONS.contains(o)) && arg.starts_with(o))
+        .max_by_key(|o| o.len())
+}
+
+/// Rewrites the paths of compile commands: normalizes them and applies prefix mappings.
+/// Covers `directory`, `file`, `output`, absolute input files and the values of
+/// path-bearing options such as `-I`, `-isystem`, `--sysroot`, `-include` and `-fprofile-use=`.
+#[derive(Debug, Clone, Default)]
+pub struct PathRewriter {
+    pub maps: Vec<PathMap>,
+    /// Resolve symbolic links of paths that exist, instead of only normalizing lexically.
+    pub canonicalize: bool,
+}
+
+impl PathRewriter {
+    /// Rewrite a single path. Relative paths are resolved against the entry's directory,
+    /// which is rewritten itself, so they only lose their `.` components: folding `..`
+    /// could skip a symbolic link that is never resolved.
+    pub fn rewrite_path(&self, path: &str) -> String {
+        self.rewrite_path_with(path, self.canonicalize)
+    }
+
+    fn rewrite_path_with(&self, path: &str, canonicalize_path: bool) -> String {
+        let path = Path::new(path);
+        if path.as_os_str().is_empty() {
+            return String::new();
+        }
+        if !path.is_absolute() {
+            let path: PathBuf = path.components().filter(|c| *c != Component::CurDir).collect();
+            return if path.as_os_str().is_empty() { ".".to_string() } else { path.to_string_lossy().into_owned() };
+        }
+        let path = if canonicalize_path { canonicalize(path) } else { normalize(path) };
+        let mapped = map_path(&path, &self.maps).unwrap_or(path);
+        mapped.to_string_lossy().into_owned()
+    }
+
+    /// Rewrite the paths in a compiler argument list.
+    /// Symbolic links of the compiler are never resolved, since drivers such as
+    /// `clang++` pick their mode from the name they are invoked with. A relative compiler
+    /// is kept as is, since `./cc` and `cc` are looked up differently.
+    pub fn rewrite_args(&self, args: &[String]) -> Vec<String> {
+        let Some((compiler, rest)) = args.split_first() else {
+            return Vec::new();
+        };
+        let compiler = if Path::new(compiler).is_absolute() {
+            self.rewrite_path_with(compiler, false)
+        } else {
+            compiler.clone()
+        };
+        let mut out = vec![compiler];
+        out.extend(map_path_args(rest, |path| self.rewrite_path(path)));
+        out
+    }
+
+    /// Rewrite all paths of a compile command in place, keeping its `command` or `arguments` form.
+    pub fn rewrite(&self, cmd: &mut CompileCommand) -> Result<(), SplitError> {
+        let args = cmd.args()?;
+        cmd.set_args(self.rewrite_args(&args));
+        cmd.directory = self.rewrite_path(&cmd.directory);
+        cmd.file = self.rewrite_path(&cmd.file);
+        if let Some(output) = &cmd.output {
+            cmd.output = Some(self.rewrite_path(output));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn rewriter(maps: &[&str]) -> PathRewriter {
+        PathRewriter { maps: maps.iter().map(|m| m.parse().unwrap()).collect(), canonicalize: false }
+    }
+
+    mod normalize_tests {
+        use super::*;
+
+        #[test]
+        fn resolves_dot_components() {
+            assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+            assert_eq!(normalize(Path::new("/a/b/../../..")), Path::new("/"));
+            assert_eq!(normalize(Path::new("../x/./y")), Path::new("../x/y"));
+            assert_eq!(normalize(Path::new("a/..")), Path::new("."));
+        }
+
+        #[test]
+        fn canonicalize_resolves_symlinks_of_existing_paths() {
+            let temp_dir = TempDir::new().unwrap();
+            let real = temp_dir.path().join("real");
+            fs::create_dir(&real).unwrap();
+            std::os::unix::fs::symlink(&real, temp_dir.path().join("link")).unwrap();
+
+            let real = fs::canonicalize(&real).unwrap();
+            assert_eq!(canonicalize(&temp_dir.path().join("link/../link")), real);
+            assert_eq!(canonicalize(Path::new("/nonexistent/a/../b")), Path::new("/nonexistent/b"));
+        }
+    }
+
+    mod path_map_tests {
+        use super::*;
+
+        #[test]
+        fn parses_mapping() {
+            let map: PathMap = "/build/src/=/home/me/src".parse().unwrap();
+            assert_eq!(map, PathMap { from: "/build/src".into(), to: "/home/me/src".into() });
+            assert!("/build/src".parse::<PathMap>().is_err());
+            assert!("=/x".parse::<PathMap>().is_err());
+        }
+
+        #[test]
+        fn maps_whole_components_only() {
+            let maps = vec!["/build/src=/home/me/src".parse().unwrap()];
+            assert_eq!(map_path(Path::new("/build/src/a.c"), &maps), Some("/home/me/src/a.c".into()));
+            assert_eq!(map_path(Path::new("/build/src"), &maps), Some("/home/me/src".into()));
+            assert_eq!(map_path(Path::new("/build/srcs/a.c"), &maps), None);
+        }
+
+        #[test]
+        fn first_matching_mapping_wins() {
+            let maps: Vec<PathMap> = vec!["/build/src/vendor=/opt/vendor".parse().unwrap(), "/build/src=/src".parse().unwrap()];
+            assert_eq!(map_path(Path::new("/build/src/vendor/z.h"), &maps), Some("/opt/vendor/z.h".into()));
+            assert_eq!(map_path(Path::new("/build/src/a.c"), &maps), Some("/src/a.c".into()));
+        }
+    }
+
+    mod rewrite_tests {
+        use super::*;
+
+        #[test]
+        fn rewrites_path_options() {
+            let r = rewriter(&["/build/src=/home/me/src"]);
+            let rewritten = r.rewrite_args(&args(&[
+                "gcc", "-I/build/src/include", "-I", "/build/src/gen", "-isystem/build/src/sys", "-isystem",
+                "/build/src/sys2", "--sysroot=/build/src/root", "--sysroot", "/build/src/root2", "-include",
+                "/build/src/config.h", "-fprofile-use=/build/src/pgo", "-c", "/build/src/a.c", "-o",
+                "/build/src/a.o", "-DPATH=/build/src",
+            ]));
+            assert_eq!(rewritten, args(&[
+                "gcc", "-I/home/me/src/include", "-I", "/home/me/src/gen", "-isystem/home/me/src/sys", "-isystem",
+                "/home/me/src/sys2", "--sysroot=/home/me/src/root", "--sysroot", "/home/me/src/root2", "-include",
+                "/home/me/src/config.h", "-fprofile-use=/home/me/src/pgo", "-c", "/home/me/src/a.c", "-o",
+                "/home/me/src/a.o", "-DPATH=/build/src",
+            ]));
+        }
+
+        #[test]
+        fn joins_paths_only_to_options_that_accept_them() {
+            let r = rewriter(&["/build=/home/me"]);
+            assert_eq!(
+                r.rewrite_args(&args(&[
+                    "cc", "-isystem/build/sys", "-MF/build/a.d", "-include-pch", "/build/a.pch", "-include/build/x.h",
+                    "-o/build/a.o",
+                ])),
+                args(&[
+                    "cc", "-isystem/home/me/sys", "-MF/home/me/a.d", "-include-pch", "/home/me/a.pch",
+                    "-include/build/x.h", "-o/home/me/a.o",
+                ])
+            );
+        }
+
+        #[test]
+        fn normalizes_without_mappings() {
+            let r = rewriter(&[]);
+            assert_eq!(
+                r.rewrite_args(&args(&["cc", "-I/a/b/../inc", "-Iinc/./x", "/a/./b.c", "-O2"])),
+                args(&["cc", "-I/a/inc", "-Iinc/x", "/a/b.c", "-O2"])
+            );
+        }
+
+        #[test]
+        fn keeps_relative_compiler_and_parent_components() {
+            let r = PathRewriter { maps: Vec::new(), canonicalize: true };
+            assert_eq!(
+                r.rewrite_args(&args(&["./cc", "-I./link/../inc", "./a.c", "-o", "."])),
+                args(&["./cc", "-Ilink/../inc", "./a.c", "-o", "."])
+            );
+            assert_eq!(r.rewrite_path("out/../../lib/./a.c"), "out/../../lib/a.c");
+        }
+
+        #[test]
+        fn rewrites_command_fields_keeping_form() {
+            let r = rewriter(&["/build/src=/home/me/src"]);
+            let mut cmd = CompileCommand::with_command("/build/src/out", "../lib/a.c", "gcc -I/build/src/inc -c ../lib/a.c");
+            cmd.output = Some("/build/src/out/a.o".to_string());
+
+            r.rewrite(&mut cmd).unwrap();
+
+            assert_eq!(cmd.directory, "/home/me/src/out");
+            assert_eq!(cmd.file, "../lib/a.c");
+            assert_eq!(cmd.output.as_deref(), Some("/home/me/src/out/a.o"));
+            assert_eq!(cmd.command.as_deref(), Some("gcc -I/home/me/src/inc -c ../lib/a.c"));
+        }
+
+        #[test]
+        fn keeps_empty_path_and_compiler_symlinks() {
+            let temp_dir = TempDir::new().unwrap();
+            let real = temp_dir.path().join("clang");
+            fs::write(&real, "").unwrap();
+            let link = temp_dir.path().join("clang++");
+            std::os::unix::fs::symlink(&real, &link).unwrap();
+            let r = PathRewriter { maps: Vec::new(), canonicalize: true };
+
+            assert_eq!(r.rewrite_path(""), "");
+            let link = link.to_str().unwrap().to_string();
+            assert_eq!(r.rewrite_args(&[link.clone(), link.clone()]), vec![link, real.to_str().unwrap().to_string()]);
+        }
+    }
+}
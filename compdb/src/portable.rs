@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+use crate::command::CompileCommand;
+use crate::paths::{map_path_args, normalize};
+use crate::shell::SplitError;
+
+/// Placeholder for the project root in exported databases.
+pub const ROOT_PLACEHOLDER: &str = "${COMPDB_ROOT}";
+
+/// Files or directories marking the root of a checkout.
+const ROOT_MARKERS: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Find the root of the checkout containing `start` by looking for version control
+/// directories in `start` and its ancestors.
+pub fn find_project_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .map(Path::to_path_buf)
+}
+
+/// How paths under the project root are written by `export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportStyle {
+    /// Replace the root with `ROOT_PLACEHOLDER`.
+    Placeholder,
+    /// Make `directory` relative to the root and other paths relative to `directory`.
+    /// Entries whose directory is outside the root use the placeholder instead.
+    Relative,
+}
+
+/// Path of `path` relative to `base`; both must be absolute and normalized.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<_> = path.components().collect();
+    let base: Vec<_> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut out = PathBuf::new();
+    for _ in common..base.len() {
+        out.push("..");
+    }
+    for component in &path[common..] {
+        out.push(component);
+    }
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+/// Rewrite absolute paths under `root` so the entry no longer depends on where the
+/// project is checked out. Paths outside the root, such as system headers, are kept.
+pub fn export(cmd: &mut CompileCommand, root: &Path, style: ExportStyle) -> Result<(), SplitError> {
+    let root = normalize(root);
+    let directory = normalize(Path::new(&cmd.directory));
+    let relative = style == ExportStyle::Relative && directory.is_absolute() && directory.starts_with(&root);
+
+    let export_path = |path: &str| -> String {
+        if !Path::new(path).is_absolute() {
+            return path.to_string();
+        }
+        let normalized = normalize(Path::new(path));
+        let Ok(rest) = normalized.strip_prefix(&root) else {
+            return path.to_string();
+        };
+        if relative {
+            relative_to(&normalized, &directory).to_string_lossy().into_owned()
+        } else if rest.as_os_str().is_empty() {
+            ROOT_PLACEHOLDER.to_string()
+        } else {
+            format!("{}/{}", ROOT_PLACEHOLDER, rest.display())
+        }
+    };
+
+    let args = cmd.args()?;
+    if let Some((compiler, rest)) = args.split_first() {
+        let mut exported_compiler = export_path(compiler);
+        // A bare name would be looked up on PATH instead of in `directory`
+        if Path::new(compiler).is_absolute() && !exported_compiler.contains('/') {
+            exported_compiler.insert_str(0, "./");
+        }
+        let mut exported = vec![exported_compiler];
+        exported.extend(map_path_args(rest, export_path));
+        cmd.set_args(exported);
+    }
+    cmd.file = export_path(&cmd.file);
+    cmd.output = cmd.output.as_deref().map(export_path);
+    cmd.directory = if relative {
+        relative_to(&directory, &root).to_string_lossy().into_owned()
+    } else {
+        export_path(&cmd.directory)
+    };
+    Ok(())
+}
+
+/// Expand an exported entry for the checkout at `root`: replace the placeholder and
+/// resolve a relative `directory` against the root.
+pub fn instantiate(cmd: &mut CompileCommand, root: &Path) -> Result<(), SplitError> {
+    let root = normalize(root);
+    let root_str = root.to_string_lossy();
+    let expand = |s: &str| s.replace(ROOT_PLACEHOLDER, &root_str);
+
+    let args = cmd.args()?;
+    cmd.set_args(args.iter().map(|arg| expand(arg)).collect());
+    cmd.file = expand(&cmd.file);
+    cmd.output = cmd.output.as_deref().map(expand);
+    let directory = expand(&cmd.directory);
+    cmd.directory = if Path::new(&directory).is_absolute() {
+        directory
+    } else {
+        normalize(&root.join(directory)).to_string_lossy().into_owned()
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample() -> CompileCommand {
+        let mut cmd = CompileCommand::with_arguments(
+            "/home/a/proj/build",
+            "/home/a/proj/src/main.c",
+            vec![
+                "/usr/bin/gcc".to_string(),
+                "-I/home/a/proj/include".to_string(),
+                "-isystem".to_string(),
+                "/usr/include/glib".to_string(),
+                "-c".to_string(),
+                "/home/a/proj/src/main.c".to_string(),
+            ],
+        );
+        cmd.output = Some("/home/a/proj/build/main.o".to_string());
+        cmd
+    }
+
+    #[test]
+    fn exports_with_placeholder() {
+        let mut cmd = sample();
+        export(&mut cmd, Path::new("/home/a/proj"), ExportStyle::Placeholder).unwrap();
+
+        assert_eq!(cmd.directory, "${COMPDB_ROOT}/build");
+        assert_eq!(cmd.file, "${COMPDB_ROOT}/src/main.c");
+        assert_eq!(cmd.output.as_deref(), Some("${COMPDB_ROOT}/build/main.o"));
+        assert_eq!(
+            cmd.arguments.unwrap(),
+            vec!["/usr/bin/gcc", "-I${COMPDB_ROOT}/include", "-isystem", "/usr/include/glib", "-c", "${COMPDB_ROOT}/src/main.c"]
+        );
+    }
+
+    #[test]
+    fn exports_relative_paths() {
+        let mut cmd = sample();
+        export(&mut cmd, Path::new("/home/a/proj/"), ExportStyle::Relative).unwrap();
+
+        assert_eq!(cmd.directory, "build");
+        assert_eq!(cmd.file, "../src/main.c");
+        assert_eq!(cmd.output.as_deref(), Some("main.o"));
+        assert_eq!(
+            cmd.arguments.unwrap(),
+            vec!["/usr/bin/gcc", "-I../include", "-isystem", "/usr/include/glib", "-c", "../src/main.c"]
+        );
+    }
+
+    #[test]
+    fn keeps_compiler_in_directory_a_path() {
+        let mut cmd = CompileCommand::with_arguments(
+            "/home/a/proj/build",
+            "/home/a/proj/a.c",
+            vec!["/home/a/proj/build/cc".to_string(), "-c".to_string(), "/home/a/proj/a.c".to_string()],
+        );
+        export(&mut cmd, Path::new("/home/a/proj"), ExportStyle::Relative).unwrap();
+
+        assert_eq!(cmd.arguments.unwrap(), vec!["./cc", "-c", "../a.c"]);
+    }
+
+    #[test]
+    fn relative_export_falls_back_to_placeholder_outside_root() {
+        let mut cmd = CompileCommand::with_command("/tmp/out", "/home/a/proj/a.c", "cc -c /home/a/proj/a.c");
+        export(&mut cmd, Path::new("/home/a/proj"), ExportStyle::Relative).unwrap();
+
+        assert_eq!(cmd.directory, "/tmp/out");
+        assert_eq!(cmd.file, "${COMPDB_ROOT}/a.c");
+        assert_eq!(cmd.command.as_deref(), Some("cc -c '${COMPDB_ROOT}/a.c'"));
+    }
+
+    #[test]
+    fn instantiates_both_styles_for_another_checkout() {
+        for style in [ExportStyle::Placeholder, ExportStyle::Relative] {
+            let mut cmd = sample();
+            export(&mut cmd, Path::new("/home/a/proj"), style).unwrap();
+            instantiate(&mut cmd, Path::new("/work/b/proj")).unwrap();
+
+            let mut expected = sample();
+            crate::paths::PathRewriter { maps: vec!["/home/a/proj=/work/b/proj".parse().unwrap()], canonicalize: false }
+                .rewrite(&mut expected)
+                .unwrap();
+            assert_eq!(cmd.directory, expected.directory, "{:?}", style);
+            // Relative exports keep relative paths, which resolve to the same files
+            let resolve = |p: &str| normalize(&Path::new(&cmd.directory).join(p));
+            assert_eq!(resolve(&cmd.file), Path::new(&expected.file));
+            assert_eq!(resolve(cmd.output.as_deref().unwrap()), Path::new(expected.output.as_deref().unwrap()));
+        }
+    }
+
+    #[test]
+    fn instantiate_keeps_absolute_entries() {
+        let mut cmd = sample();
+        instantiate(&mut cmd, Path::new("/elsewhere")).unwrap();
+        assert_eq!(cmd, sample());
+    }
+
+    #[test]
+    fn finds_project_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let nested = temp_dir.path().join("a/b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir(temp_dir.path().join(".git")).unwrap();
+
+        assert_eq!(find_project_root(&nested), Some(temp_dir.path().to_path_buf()));
+    }
+}
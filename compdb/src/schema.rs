@@ -0,0 +1,213 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::encoding::{self, LogString};
+
+/// Schema version written by this wrapper, stored in the `v` field of every record.
+/// Records without it were written by older wrappers and are version 0. Optional
+/// fields can be added with `#[serde(default)]` without bumping the version.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Compiler assumed for version 0 records that don't specify one.
+pub const DEFAULT_COMPILER: &str = "/usr/bin/gcc";
+
+/// Error returned for a record that cannot be read with any known schema.
+#[derive(Debug, PartialEq)]
+pub enum SchemaError {
+    /// The record is not valid JSON or does not match its schema version.
+    Invalid,
+    /// The record was written by a newer wrapper with an unknown schema version.
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::Invalid => write!(f, "invalid log entry"),
+            SchemaError::UnsupportedVersion(v) => {
+                write!(f, "unsupported log schema version {} (newest known is {})", v, SCHEMA_VERSION)
+            }
+        }
+    }
+}
+
+/// Version 0: `{"wd": ..., "compiler": ..., "args": [...]}` without a version field.
+/// The earliest wrappers didn't record the compiler, and values that are neither
+/// strings nor hex objects were always skipped, so this version is parsed leniently.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LogEntryV0 {
+    #[serde(default)]
+    pub wd: Option<Value>,
+    #[serde(default)]
+    pub compiler: Option<Value>,
+    pub args: Vec<Value>,
+}
+
+/// Version 1: the version 0 fields with a version tag and strict typing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntryV1 {
+    pub v: u64,
+    pub wd: LogString,
+    pub compiler: LogString,
+    pub args: Vec<LogString>,
+}
+
+/// A log record in the current schema version.
+pub type LogEntry = LogEntryV1;
+
+impl LogEntryV1 {
+    /// Create a record in the current schema version.
+    pub fn new(wd: LogString, compiler: LogString, args: Vec<LogString>) -> Self {
+        LogEntryV1 { v: SCHEMA_VERSION, wd, compiler, args }
+    }
+}
+
+impl From<LogEntryV0> for LogEntryV1 {
+    fn from(old: LogEntryV0) -> Self {
+        let decode = |value: Option<Value>| value.as_ref().and_then(encoding::decode_os).map(LogString);
+        LogEntryV1::new(
+            decode(old.wd).unwrap_or_default(),
+            decode(old.compiler).unwrap_or_else(|| LogString::from(DEFAULT_COMPILER)),
+            old.args.iter().filter_map(encoding::decode_os).map(LogString).collect(),
+        )
+    }
+}
+
+/// Parse a log record of any known schema version and migrate it to the current one.
+pub fn parse_entry(line: &str) -> Result<LogEntry, SchemaError> {
+    let value: Value = serde_json::from_str(line).map_err(|_| SchemaError::Invalid)?;
+    let version = match value.get("v") {
+        None => 0,
+        Some(v) => v.as_u64().ok_or(SchemaError::Invalid)?,
+    };
+    match version {
+        0 => {
+            let old: LogEntryV0 = serde_json::from_value(value).map_err(|_| SchemaError::Invalid)?;
+            Ok(old.into())
+        }
+        1 => serde_json::from_value(value).map_err(|_| SchemaError::Invalid),
+        v => Err(SchemaError::UnsupportedVersion(v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    fn strings(entry: &LogEntry) -> (String, String, Vec<String>) {
+        let s = |l: &LogString| l.0.to_str().unwrap().to_string();
+        (s(&entry.wd), s(&entry.compiler), entry.args.iter().map(s).collect())
+    }
+
+    mod version_0_tests {
+        use super::*;
+
+        #[test]
+        fn migrates_entry_without_compiler() {
+            let entry = parse_entry(r#"{"wd":"/project","args":["-c","main.c"]}"#).unwrap();
+            assert_eq!(entry.v, SCHEMA_VERSION);
+            assert_eq!(
+                strings(&entry),
+                ("/project".to_string(), DEFAULT_COMPILER.to_string(), vec!["-c".to_string(), "main.c".to_string()])
+            );
+        }
+
+        #[test]
+        fn keeps_recorded_compiler() {
+            let entry = parse_entry(r#"{"wd":"/p","compiler":"clang","args":[]}"#).unwrap();
+            assert_eq!(strings(&entry).1, "clang");
+        }
+
+        #[test]
+        fn defaults_missing_wd_to_empty() {
+            let entry = parse_entry(r#"{"args":["-c","main.c"]}"#).unwrap();
+            assert_eq!(strings(&entry).0, "");
+        }
+
+        #[test]
+        fn skips_non_string_args() {
+            let entry = parse_entry(r#"{"wd":"/p","args":["-c",123,"main.c",null]}"#).unwrap();
+            assert_eq!(strings(&entry).2, vec!["-c", "main.c"]);
+        }
+
+        #[test]
+        fn decodes_hex_values() {
+            let entry = parse_entry(r#"{"wd":"/p","args":[{"hex":"ff"}]}"#).unwrap();
+            assert_eq!(entry.args[0].0, OsString::from_vec(vec![0xff]));
+        }
+
+        #[test]
+        fn rejects_missing_or_invalid_args() {
+            assert_eq!(parse_entry(r#"{"wd":"/p"}"#), Err(SchemaError::Invalid));
+            assert_eq!(parse_entry(r#"{"wd":"/p","args":"-c"}"#), Err(SchemaError::Invalid));
+            assert_eq!(parse_entry("not json"), Err(SchemaError::Invalid));
+        }
+    }
+
+    mod version_1_tests {
+        use super::*;
+
+        #[test]
+        fn parses_entry() {
+            let entry = parse_entry(r#"{"v":1,"wd":"/p","compiler":"gcc","args":["-c","a.c"]}"#).unwrap();
+            assert_eq!(
+                strings(&entry),
+                ("/p".to_string(), "gcc".to_string(), vec!["-c".to_string(), "a.c".to_string()])
+            );
+        }
+
+        #[test]
+        fn ignores_unknown_fields() {
+            let line = r#"{"v":1,"wd":"/p","compiler":"gcc","args":[],"env":{"A":"1"},"exit":0}"#;
+            assert!(parse_entry(line).is_ok());
+        }
+
+        #[test]
+        fn requires_compiler() {
+            assert_eq!(parse_entry(r#"{"v":1,"wd":"/p","args":[]}"#), Err(SchemaError::Invalid));
+        }
+
+        #[test]
+        fn rejects_non_string_args() {
+            assert_eq!(
+                parse_entry(r#"{"v":1,"wd":"/p","compiler":"gcc","args":[1]}"#),
+                Err(SchemaError::Invalid)
+            );
+        }
+
+        #[test]
+        fn serializes_with_version_first() {
+            let entry = LogEntry::new("/p".into(), "gcc".into(), vec!["-c".into()]);
+            assert_eq!(
+                serde_json::to_string(&entry).unwrap(),
+                r#"{"v":1,"wd":"/p","compiler":"gcc","args":["-c"]}"#
+            );
+        }
+
+        #[test]
+        fn roundtrips() {
+            let entry = LogEntry::new("/p".into(), "gcc".into(), vec![LogString(OsString::from_vec(vec![0x80]))]);
+            let line = serde_json::to_string(&entry).unwrap();
+            assert_eq!(parse_entry(&line).unwrap(), entry);
+        }
+    }
+
+    mod version_check_tests {
+        use super::*;
+
+        #[test]
+        fn rejects_newer_version() {
+            assert_eq!(
+                parse_entry(r#"{"v":7,"wd":"/p","compiler":"gcc","args":[]}"#),
+                Err(SchemaError::UnsupportedVersion(7))
+            );
+        }
+
+        #[test]
+        fn rejects_non_numeric_version() {
+            assert_eq!(parse_entry(r#"{"v":"1","wd":"/p","args":[]}"#), Err(SchemaError::Invalid));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// Error returned for a command line that cannot be split into arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitError {
+    /// A single or double quote is never closed.
+    UnterminatedQuote(char),
+    /// The command line ends with an escaping backslash.
+    TrailingBackslash,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::UnterminatedQuote(q) => write!(f, "unterminated {} quote", q),
+            SplitError::TrailingBackslash => write!(f, "trailing backslash"),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// Split a command line into arguments following POSIX shell quoting rules.
+/// Supports single quotes, double quotes and backslash escapes; no expansion of
+/// variables, globs or command substitutions is done.
+pub fn split(command: &str) -> Result<Vec<String>, SplitError> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // Distinguishes an empty quoted argument from no argument at all
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            '\\' => match chars.next() {
+                // A backslash-newline pair continues the line
+                Some('\n') => {}
+                Some(escaped) => {
+                    current.push(escaped);
+                    in_word = true;
+                }
+                None => return Err(SplitError::TrailingBackslash),
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err(SplitError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        // Inside double quotes a backslash only escapes these characters
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(escaped @ ('\\' | '"' | '$' | '`')) => current.push(escaped),
+                            Some(other) => {
+                                current.push('\\');
+                                current.push(other);
+                            }
+                            None => return Err(SplitError::UnterminatedQuote('"')),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err(SplitError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Quote an argument so that `split` (or a POSIX shell) reads it back unchanged.
+/// Arguments consisting only of safe characters are returned as they are.
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=/.,:@%^".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+/// Join arguments into a command line, quoting them where needed.
+pub fn join<S: AsRef<str>>(args: &[S]) -> String {
+    args.iter()
+        .map(|arg| quote(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod split_tests {
+        use super::*;
+
+        #[test]
+        fn splits_on_whitespace() {
+            assert_eq!(split("gcc  -c\tmain.c\n").unwrap(), vec!["gcc", "-c", "main.c"]);
+        }
+
+        #[test]
+        fn returns_nothing_for_blank_command() {
+            assert!(split("  ").unwrap().is_empty());
+        }
+
+        #[test]
+        fn handles_single_quotes() {
+            assert_eq!(split(r#"gcc 'my file.c' '$HOME\'"#).unwrap(), vec!["gcc", "my file.c", r"$HOME\"]);
+        }
+
+        #[test]
+        fn handles_double_quotes() {
+            assert_eq!(
+                split(r#"gcc -DVERSION="\"1.0\"" "a\b.c""#).unwrap(),
+                vec!["gcc", r#"-DVERSION="1.0""#, r"a\b.c"]
+            );
+        }
+
+        #[test]
+        fn handles_backslash_escapes() {
+            assert_eq!(split(r"gcc my\ file.c \'x").unwrap(), vec!["gcc", "my file.c", "'x"]);
+        }
+
+        #[test]
+        fn joins_line_continuations() {
+            assert_eq!(split("gcc -c \\\nmain.c").unwrap(), vec!["gcc", "-c", "main.c"]);
+        }
+
+        #[test]
+        fn keeps_empty_quoted_arguments() {
+            assert_eq!(split(r#"a '' """#).unwrap(), vec!["a", "", ""]);
+        }
+
+        #[test]
+        fn concatenates_adjacent_segments() {
+            assert_eq!(split(r#"-D'A'"B"C"#).unwrap(), vec!["-DABC"]);
+        }
+
+        #[test]
+        fn rejects_unterminated_quotes() {
+            assert_eq!(split("gcc 'main.c"), Err(SplitError::UnterminatedQuote('\'')));
+            assert_eq!(split("gcc \"main.c"), Err(SplitError::UnterminatedQuote('"')));
+            assert_eq!(split("gcc main.c\\"), Err(SplitError::TrailingBackslash));
+        }
+    }
+
+    mod quote_tests {
+        use super::*;
+
+        #[test]
+        fn leaves_safe_arguments_unquoted() {
+            assert!(matches!(quote("-I/usr/include"), Cow::Borrowed(_)));
+            assert_eq!(quote("-DX=1"), "-DX=1");
+        }
+
+        #[test]
+        fn quotes_unsafe_arguments() {
+            assert_eq!(quote("my file.c"), "'my file.c'");
+            assert_eq!(quote(""), "''");
+            assert_eq!(quote("it's"), r"'it'\''s'");
+        }
+
+        #[test]
+        fn join_roundtrips_through_split() {
+            let args = ["gcc", "-DMSG=\"hi there\"", "it's", "", "$HOME", "a\\b", "tab\there", "файл.c"];
+            assert_eq!(split(&join(&args)).unwrap(), args);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use compdb::{output, paths};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A backup of a database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+/// The backups of a database: `<name>.bak`, `<name>.bak.1`, `<name>.bak.2`, etc. in the
+/// database's directory, or `<name>.<hash>.bak` and so on in a backup directory, which
+/// may be shared by several databases. Backups are ordered by modification time, since
+/// retention can free names of old backups for new ones.
+#[derive(Debug, Clone)]
+pub struct Backups {
+    dir: PathBuf,
+    /// File name of the first backup, `<name>.bak`.
+    base: OsString,
+}
+
+impl Backups {
+    pub fn new(database: &Path, backup_dir: Option<&Path>) -> Self {
+        let dir = match (backup_dir, database.parent()) {
+            (Some(dir), _) => dir.to_path_buf(),
+            (None, Some(parent)) => parent.to_path_buf(),
+            (None, None) => PathBuf::new(),
+        };
+        let mut base = database.file_name().unwrap_or(database.as_os_str()).to_os_string();
+        if backup_dir.is_some() {
+            base.push(format!(".{}", path_hash(database)));
+        }
+        base.push(".bak");
+        Backups { dir, base }
+    }
+
+    /// The first backup path that doesn't exist.
+    pub fn next_path(&self) -> PathBuf {
+        let base = self.dir.join(&self.base);
+        if !base.exists() {
+            return base;
+        }
+        (1..)
+            .map(|n| {
+                let mut name = self.base.clone();
+                name.push(format!(".{}", n));
+                self.dir.join(name)
+            })
+            .find(|path| !path.exists())
+            .expect("some backup number is free")
+    }
+
+    /// Check if a file name is one of the backup names.
+    fn is_backup_name(&self, name: &OsStr) -> bool {
+        let (Some(name), Some(base)) = (name.to_str(), self.base.to_str()) else {
+            return false;
+        };
+        match name.strip_prefix(base) {
+            Some("") => true,
+            Some(rest) => rest.strip_prefix('.').is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())),
+            None => false,
+        }
+    }
+
+    /// List the backups, most recent first.
+    pub fn list(&self) -> Result<Vec<Backup>, String> {
+        let entries = match fs::read_dir(if self.dir.as_os_str().is_empty() { Path::new(".") } else { &self.dir }) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("failed to read {}: {}", self.dir.display(), e)),
+        };
+        let mut backups: Vec<Backup> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| self.is_backup_name(&entry.file_name()))
+            .filter_map(|entry| {
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                Some(Backup { path: self.dir.join(entry.file_name()), modified })
+            })
+            .collect();
+        backups.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| b.path.cmp(&a.path)));
+        Ok(backups)
+    }
+
+    /// Copy the database to the next backup path.
+    pub fn create(&self, database: &Path) -> Result<PathBuf, String> {
+        if !self.dir.as_os_str().is_empty() {
+            fs::create_dir_all(&self.dir).map_err(|e| format!("failed to create {}: {}", self.dir.display(), e))?;
+        }
+        let path = self.next_path();
+        fs::copy(database, &path).map_err(|e| format!("failed to back up to {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// Put a backup in place of the database, keeping the replaced database as the most
+    /// recent backup so restoring it again undoes the restore. The database is first
+    /// copied to a new backup, then replaced atomically, and only then is the restored
+    /// backup removed, so an interruption never loses either version. Returns the path
+    /// of the new backup.
+    pub fn restore(&self, database: &Path, backup: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let restored = fs::read(backup).map_err(|e| format!("failed to read {}: {}", backup.display(), e))?;
+        let saved = self.create(database)?;
+        output::write_atomic(database, |out| Ok(out.write_all(&restored)?))?;
+        fs::remove_file(backup).map_err(|e| format!("failed to remove {}: {}", backup.display(), e))?;
+        Ok(saved)
+    }
+
+    /// Remove all but the `keep` most recent backups, returning the removed paths.
+    pub fn prune(&self, keep: usize) -> Result<Vec<PathBuf>, String> {
+        let mut removed = Vec::new();
+        for backup in self.list()?.into_iter().skip(keep) {
+            fs::remove_file(&backup.path).map_err(|e| format!("failed to remove {}: {}", backup.path.display(), e))?;
+            removed.push(backup.path);
+        }
+        Ok(removed)
+    }
+}
+
+/// A short hash of the canonical path of a database, the same across runs and builds.
+fn path_hash(database: &Path) -> String {
+    let path = std::path::absolute(database).map_or_else(|_| database.to_path_buf(), |path| paths::canonicalize(&path));
+    // 32-bit FNV-1a
+    let hash = path.as_os_str().as_encoded_bytes().iter().fold(0x811c9dc5u32, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x01000193)
+    });
+    format!("{:08x}", hash)
+}
+
+
+/// Format a time as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn set_modified(path: &Path, secs: u64) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    }
+
+    #[test]
+    fn lists_backups_by_time_and_prunes_old_ones() {
+        let temp_dir = TempDir::new().unwrap();
+        let database = temp_dir.path().join("compile_commands.json");
+        fs::write(&database, "[]").unwrap();
+        let backups = Backups::new(&database, None);
+        for (suffix, secs) in [(".1", 300), ("", 100), (".2", 200)] {
+            let path = temp_dir.path().join(format!("compile_commands.json.bak{}", suffix));
+            fs::write(&path, "[]").unwrap();
+            set_modified(&path, secs);
+        }
+        fs::write(temp_dir.path().join("compile_commands.json.bak.x"), "").unwrap();
+
+        let names = |list: Vec<Backup>| -> Vec<String> {
+            list.iter().map(|b| b.path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+        };
+        assert_eq!(
+            names(backups.list().unwrap()),
+            ["compile_commands.json.bak.1", "compile_commands.json.bak.2", "compile_commands.json.bak"]
+        );
+
+        assert_eq!(backups.prune(2).unwrap(), vec![temp_dir.path().join("compile_commands.json.bak")]);
+        // The freed name is reused, and the new backup is still the most recent
+        let created = backups.create(&database).unwrap();
+        assert_eq!(created, temp_dir.path().join("compile_commands.json.bak"));
+        assert_eq!(backups.list().unwrap()[0].path, created);
+    }
+
+    #[test]
+    fn creates_backups_in_backup_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let database = temp_dir.path().join("compile_commands.json");
+        fs::write(&database, "[]").unwrap();
+        let backup_dir = temp_dir.path().join("backups");
+        let backups = Backups::new(&database, Some(&backup_dir));
+        let name = format!("compile_commands.json.{}.bak", path_hash(&database));
+
+        assert_eq!(backups.create(&database).unwrap(), backup_dir.join(&name));
+        assert_eq!(backups.create(&database).unwrap(), backup_dir.join(format!("{}.1", name)));
+        assert!(Backups::new(&database, None).list().unwrap().is_empty());
+
+        // Another project sharing the backup directory has its own series
+        let other = temp_dir.path().join("other/compile_commands.json");
+        fs::create_dir(other.parent().unwrap()).unwrap();
+        fs::write(&other, "[]").unwrap();
+        let other_backups = Backups::new(&other, Some(&backup_dir));
+        assert!(other_backups.list().unwrap().is_empty());
+        assert_ne!(other_backups.create(&other).unwrap(), backup_dir.join(&name));
+        assert_eq!(backups.list().unwrap().len(), 2);
+        // Different paths to the same database share its backups
+        assert_eq!(path_hash(&temp_dir.path().join("other/../compile_commands.json")), path_hash(&database));
+    }
+
+    #[test]
+    fn restores_backup_keeping_database_as_new_backup() {
+        let temp_dir = TempDir::new().unwrap();
+        let database = temp_dir.path().join("db.json");
+        let backup = temp_dir.path().join("db.json.bak");
+        fs::write(&database, "new").unwrap();
+        fs::write(&backup, "old").unwrap();
+        let backups = Backups::new(&database, None);
+
+        let saved = backups.restore(&database, &backup).unwrap();
+        assert_eq!(saved, temp_dir.path().join("db.json.bak.1"));
+        assert_eq!(fs::read_to_string(&database).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "new");
+        assert!(!backup.exists());
+        assert_eq!(backups.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn formats_times_in_utc() {
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01 00:00:00");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(951_782_400 + 3661)), "2000-02-29 01:01:01");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(1_790_000_000)), "2026-09-21 14:13:20");
+    }
+}
@@ -0,0 +1,132 @@
+use compdb::{depfile, paths, CompileCommand};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Which changes of a git checkout select files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitChanges {
+    /// Files changed in a revision range, e.g. `origin/main...HEAD`.
+    Range(String),
+    /// Files changed in the index.
+    Staged,
+    /// Files changed in the working tree since `HEAD`, staged or not, and untracked files.
+    Worktree,
+}
+
+/// Run git in `dir` and return its stdout.
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {} failed: {}", args.join(" "), stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Find the top of the git checkout containing the first of `dirs` that is in one.
+/// Fails with the error for the first directory if none is.
+pub fn git_toplevel(dirs: &[PathBuf]) -> Result<PathBuf, String> {
+    let mut first_error = None;
+    for dir in dirs {
+        match git(dir, &["rev-parse", "--show-toplevel"]) {
+            Ok(top) => return Ok(PathBuf::from(top.trim_end_matches('\n'))),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| "no directory to run git in".to_string()))
+}
+
+/// List the files changed in the git checkout at `top`, as absolute paths.
+pub fn git_changed_files(top: &Path, changes: &GitChanges) -> Result<Vec<PathBuf>, String> {
+    let mut listed = match changes {
+        // The range is never an option, even if it starts with a dash
+        GitChanges::Range(range) => git(top, &["diff", "--name-only", "-z", "--end-of-options", range, "--"])?,
+        GitChanges::Staged => git(top, &["diff", "--name-only", "-z", "--cached"])?,
+        GitChanges::Worktree => git(top, &["diff", "--name-only", "-z", "HEAD"])?,
+    };
+    if *changes == GitChanges::Worktree {
+        listed.push_str(&git(top, &["ls-files", "-z", "--others", "--exclude-standard"])?);
+    }
+    Ok(listed.split('\0').filter(|f| !f.is_empty()).map(|f| top.join(f)).collect())
+}
+
+/// Read a list of files, one per line, relative to the current directory. Blank lines
+/// are ignored.
+pub fn read_file_list(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| std::path::absolute(line).map_err(|e| format!("{}: {}: {}", path.display(), line, e)))
+        .collect()
+}
+
+/// Selects the entries for a set of files, such as the files changed in a commit.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    files: HashSet<PathBuf>,
+    /// Also select entries whose dependency file lists a selected file, so changed
+    /// headers select the sources including them.
+    pub dependents: bool,
+}
+
+impl Selection {
+    pub fn new(files: impl IntoIterator<Item = PathBuf>, dependents: bool) -> Self {
+        // Canonical paths, so paths through symbolic links, as git reports them, compare equal
+        let files = files.into_iter().map(|file| paths::canonicalize(&file)).collect();
+        Selection { files, dependents }
+    }
+
+    /// Check if an entry is for a selected file, or depends on one if `dependents` is set.
+    /// Entries without a readable dependency file only match by their source.
+    pub fn selects(&self, cmd: &CompileCommand) -> bool {
+        if self.files.contains(&paths::canonicalize(&cmd.file_path())) {
+            return true;
+        }
+        if !self.dependents {
+            return false;
+        }
+        let Some(content) = depfile::path(cmd).and_then(|path| fs::read_to_string(path).ok()) else {
+            return false;
+        };
+        let directory = Path::new(&cmd.directory);
+        depfile::parse(&content).iter().any(|dep| self.files.contains(&paths::canonicalize(&directory.join(dep))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn selects_changed_sources_and_dependents() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("a.d"), "a.o: src/a.c include/util.h\n").unwrap();
+        let dir_str = dir.to_str().unwrap();
+        let a = CompileCommand::with_command(dir_str, "src/a.c", "cc -MMD -c src/a.c -o a.o");
+        let b = CompileCommand::with_command(dir_str, "src/b.c", "cc -MMD -c src/b.c -o b.o");
+
+        let selection = Selection::new([dir.join("src/b.c")], false);
+        assert!(!selection.selects(&a));
+        assert!(selection.selects(&b));
+
+        let selection = Selection::new([dir.join("include/./util.h")], false);
+        assert!(!selection.selects(&a));
+        let selection = Selection { dependents: true, ..selection };
+        assert!(selection.selects(&a));
+        // b.d doesn't exist
+        assert!(!selection.selects(&b));
+    }
+}
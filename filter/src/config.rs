@@ -0,0 +1,160 @@
+use crate::matcher::Syntax;
+use crate::MatchPath;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the project configuration file.
+pub const CONFIG_FILE: &str = ".compdb.toml";
+
+/// A named set of filter settings, selected with `--profile`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    /// Syntax of the profile's file patterns [default: regex].
+    pub syntax: Option<Syntax>,
+    pub match_path: Option<MatchPath>,
+    pub rules: Vec<String>,
+    pub exclude: Vec<String>,
+    pub include: Vec<String>,
+}
+
+/// The contents of a configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// Profiles read from a TOML configuration file:
+///
+/// ```toml
+/// [profiles.no-tests]
+/// syntax = "gitignore"
+/// match-path = "root"
+/// exclude = ["test/", "tests/"]
+/// include = ["tests/fixtures/"]
+/// rules = ["+ **/keep/**"]
+/// ```
+///
+/// Tables other than `profiles` and unknown keys are errors, so typos don't silently
+/// change what is filtered.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub path: PathBuf,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Find the configuration file in `start` or its ancestors.
+pub fn find_config(start: &Path) -> Option<PathBuf> {
+    start.ancestors().map(|dir| dir.join(CONFIG_FILE)).find(|path| path.is_file())
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let file: ConfigFile = toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Config { path: path.to_path_buf(), profiles: file.profiles })
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            let names: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+            format!("no profile {:?} in {} (profiles: {})", name, self.path.display(), names.join(", "))
+        })
+    }
+}
+
+/// Read a pattern list file with one pattern per line. Blank lines and lines starting
+/// with `#` are ignored.
+pub fn load_patterns(path: &Path) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn parse(content: &str) -> Result<BTreeMap<String, Profile>, String> {
+        toml::from_str::<ConfigFile>(content).map(|file| file.profiles).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let profiles = parse(
+            r#"
+# Shared filters
+[profiles.no-tests]
+syntax = "gitignore"   # patterns as in .gitignore
+match-path = 'root'
+exclude = [
+    "test/",
+    'tests/',  # trailing comma
+]
+
+[ profiles."kernel-x86" ]
+rules = ["- ^arch/(arm|mips)/", '+ "quoted\"', """
++ \u00e9t\u00e9/"""]
+
+[profiles]
+ours-only = { exclude = ['''^third_party/'''] }
+vendor.include = ["^vendor/"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            profiles["no-tests"],
+            Profile {
+                syntax: Some(Syntax::Gitignore),
+                match_path: Some(MatchPath::Root),
+                exclude: vec!["test/".to_string(), "tests/".to_string()],
+                ..Profile::default()
+            }
+        );
+        assert_eq!(profiles["kernel-x86"].rules, vec!["- ^arch/(arm|mips)/", "+ \"quoted\\\"", "+ \u{e9}t\u{e9}/"]);
+        assert_eq!(profiles["ours-only"].exclude, vec!["^third_party/"]);
+        assert_eq!(profiles["vendor"].include, vec!["^vendor/"]);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = |content: &str| parse(content).unwrap_err();
+        assert!(error("exclude = []").contains("unknown field `exclude`"));
+        assert!(error("[profiles.a]\n\nexlude = []").contains("line 3"));
+        assert!(error("[tool.a]").contains("unknown field `tool`"));
+        assert!(error("[profiles.a]\nexclude = \"x\"").contains("expected a sequence"));
+        assert!(error("[profiles.a]\nsyntax = \"glb\"").contains("unknown variant `glb`"));
+        assert!(error("[profiles.a]\nexclude = [\"x\"").contains("line 2"));
+        assert!(error("[profiles.a]\n[profiles.a]").contains("duplicate"));
+    }
+
+    #[test]
+    fn finds_config_and_profiles() {
+        let temp_dir = TempDir::new().unwrap();
+        let nested = temp_dir.path().join("build/x");
+        fs::create_dir_all(&nested).unwrap();
+        let path = temp_dir.path().join(CONFIG_FILE);
+        fs::write(&path, "[profiles.ours-only]\nexclude = [\"^third_party/\"]\n").unwrap();
+
+        assert_eq!(find_config(&nested), Some(path.clone()));
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.profile("ours-only").unwrap().exclude, vec!["^third_party/"]);
+        assert!(config.profile("no-tests").unwrap_err().contains("profiles: ours-only"));
+    }
+
+    #[test]
+    fn loads_pattern_lists() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("exclude.txt");
+        fs::write(&path, "# generated code\n^gen/\n\n  **/test/**  \n").unwrap();
+        assert_eq!(load_patterns(&path).unwrap(), vec!["^gen/", "**/test/**"]);
+    }
+}
@@ -0,0 +1,216 @@
+use compdb::shell::{self, SplitError};
+use compdb::{flags, CompileCommand};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Which compiler arguments a flag edit applies to: `FLAG` exactly, `FLAG*` by prefix,
+/// or `/REGEX/`.
+#[derive(Debug, Clone)]
+pub enum FlagPattern {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl FromStr for FlagPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(re) = s.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+            return Regex::new(re).map(FlagPattern::Regex).map_err(|e| format!("invalid pattern {:?}: {}", s, e));
+        }
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err(format!("invalid flag pattern {:?}, expected FLAG, FLAG* or /REGEX/", s));
+        }
+        Ok(match s.strip_suffix('*') {
+            Some(prefix) => FlagPattern::Prefix(prefix.to_string()),
+            None => FlagPattern::Exact(s.to_string()),
+        })
+    }
+}
+
+impl FlagPattern {
+    pub fn matches(&self, arg: &str) -> bool {
+        match self {
+            FlagPattern::Exact(flag) => arg == flag,
+            FlagPattern::Prefix(prefix) => arg.starts_with(prefix.as_str()),
+            FlagPattern::Regex(re) => re.is_match(arg),
+        }
+    }
+}
+
+/// An edit of compiler arguments, written `add ARGS`, `remove PATTERN` or
+/// `replace PATTERN => ARGS`, with `ARGS` shell-quoted.
+#[derive(Debug, Clone)]
+pub enum FlagEdit {
+    /// Append arguments.
+    Add(Vec<String>),
+    /// Remove matching arguments, with the value of options that take a separate one.
+    Remove(FlagPattern),
+    /// Replace matching arguments. The value of an option that takes a separate one
+    /// is kept, so `replace -I => -isystem` turns `-I inc` into `-isystem inc`.
+    Replace(FlagPattern, Vec<String>),
+}
+
+impl FromStr for FlagEdit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("invalid flag edit {:?}, expected add ARGS, remove PATTERN or replace PATTERN => ARGS", s);
+        let split = |args: &str| shell::split(args).map_err(|_| invalid());
+        let (op, rest) = s.trim().split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let rest = rest.trim();
+        match op {
+            "add" => Ok(FlagEdit::Add(split(rest)?)),
+            "remove" => Ok(FlagEdit::Remove(rest.parse()?)),
+            "replace" => {
+                let (pattern, args) = rest.split_once("=>").ok_or_else(invalid)?;
+                Ok(FlagEdit::Replace(pattern.trim().parse()?, split(args)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Read flag edits from a file with one edit per line. Blank lines and lines starting
+/// with `#` are ignored.
+pub fn load_edits(path: &Path) -> Result<Vec<FlagEdit>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| line.parse().map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e)))
+        .collect()
+}
+
+impl FlagEdit {
+    /// Apply the edit to compiler arguments without the compiler. Values of options
+    /// that take a separate value are never matched themselves.
+    fn apply(&self, args: Vec<String>) -> Vec<String> {
+        let (pattern, replacement) = match self {
+            FlagEdit::Add(added) => return args.into_iter().chain(added.iter().cloned()).collect(),
+            FlagEdit::Remove(pattern) => (pattern, None),
+            FlagEdit::Replace(pattern, replacement) => (pattern, Some(replacement)),
+        };
+        let mut out = Vec::with_capacity(args.len());
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = if flags::takes_value(&arg) { args.next() } else { None };
+            if !pattern.matches(&arg) {
+                out.push(arg);
+                out.extend(value);
+            } else if let Some(replacement) = replacement {
+                out.extend(replacement.iter().cloned());
+                out.extend(value);
+            }
+        }
+        out
+    }
+}
+
+/// Applies flag edits in order to compile commands.
+#[derive(Debug, Clone, Default)]
+pub struct FlagEditor {
+    pub edits: Vec<FlagEdit>,
+}
+
+impl FlagEditor {
+    /// Edit a compiler argument list, starting with the compiler, which is kept.
+    pub fn rewrite_args(&self, args: &[String]) -> Vec<String> {
+        let Some((compiler, rest)) = args.split_first() else {
+            return Vec::new();
+        };
+        let edited = self.edits.iter().fold(rest.to_vec(), |args, edit| edit.apply(args));
+        std::iter::once(compiler.clone()).chain(edited).collect()
+    }
+
+    /// Edit the flags of a compile command in place, keeping its `command` or `arguments` form.
+    pub fn rewrite(&self, cmd: &mut CompileCommand) -> Result<(), SplitError> {
+        let args = cmd.args()?;
+        cmd.set_args(self.rewrite_args(&args));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn edit(edits: &[&str], list: &[&str]) -> Vec<String> {
+        let editor = FlagEditor { edits: edits.iter().map(|e| e.parse().unwrap()).collect() };
+        editor.rewrite_args(&args(list))
+    }
+
+    #[test]
+    fn parses_edits() {
+        let parse = |s: &str| s.parse::<FlagEdit>();
+        assert!(matches!(parse("add '-DX=a b' -w"), Ok(FlagEdit::Add(a)) if a == ["-DX=a b", "-w"]));
+        assert!(matches!(parse("remove -Werror=*"), Ok(FlagEdit::Remove(FlagPattern::Prefix(p))) if p == "-Werror="));
+        assert!(matches!(parse("remove /^-flto(=|$)/"), Ok(FlagEdit::Remove(FlagPattern::Regex(_)))));
+        assert!(matches!(
+            parse("replace -std=gnu89 => -std=gnu11"),
+            Ok(FlagEdit::Replace(FlagPattern::Exact(_), r)) if r == ["-std=gnu11"]
+        ));
+        assert!(parse("drop -Werror").is_err());
+        assert!(parse("remove").is_err());
+        assert!(parse("replace -O2").is_err());
+        assert!(parse("remove /[/").is_err());
+    }
+
+    #[test]
+    fn adds_removes_and_replaces_flags() {
+        let edits = [
+            "remove -Werror",
+            "remove -Werror=*",
+            "remove /^-flto(=|$)/",
+            "replace -std=gnu89 => -std=gnu11",
+            "add -Wno-unknown-warning-option",
+        ];
+        let input = ["gcc", "-Werror", "-Werror=format", "-flto=thin", "-flto", "-fltofoo", "-std=gnu89", "-c", "a.c"];
+        assert_eq!(
+            edit(&edits, &input),
+            args(&["gcc", "-fltofoo", "-std=gnu11", "-c", "a.c", "-Wno-unknown-warning-option"])
+        );
+    }
+
+    #[test]
+    fn handles_separate_values() {
+        let input = ["gcc", "-include", "pch.h", "-o", "-Obj.o", "-O2", "-c", "a.c"];
+        assert_eq!(
+            edit(&["remove -include", "remove /^-O/"], &input),
+            args(&["gcc", "-o", "-Obj.o", "-c", "a.c"])
+        );
+        assert_eq!(
+            edit(&["replace -I => -isystem"], &["gcc", "-I", "inc", "-Igen", "-c", "a.c"]),
+            args(&["gcc", "-isystem", "inc", "-Igen", "-c", "a.c"])
+        );
+    }
+
+    #[test]
+    fn keeps_command_form() {
+        let mut cmd = CompileCommand::with_command("/b", "a.c", "gcc -Werror -c a.c");
+        FlagEditor { edits: vec!["remove -Werror".parse().unwrap()] }.rewrite(&mut cmd).unwrap();
+        assert_eq!(cmd.command.as_deref(), Some("gcc -c a.c"));
+    }
+
+    #[test]
+    fn loads_edits_from_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("edits");
+        fs::write(&path, "# for clangd\nremove -Werror\n\nadd '-DX=a b'\n").unwrap();
+        assert_eq!(load_edits(&path).unwrap().len(), 2);
+
+        fs::write(&path, "remove -Werror\nremove\n").unwrap();
+        assert!(load_edits(&path).unwrap_err().contains("edits:2"));
+    }
+}
@@ -1,54 +1,235 @@
-use clap::Parser;
+mod backup;
+mod changes;
+mod config;
+mod edit;
+mod matcher;
+mod rules;
+mod stale;
+
+use backup::Backups;
+use changes::{GitChanges, Selection};
+use clap::{ArgGroup, Parser, ValueEnum};
+use compdb::compat::{self, Compat, Rule};
+use compdb::database::{self, Writer};
+use compdb::paths::{self, PathMap, PathRewriter};
+use compdb::portable::{self, ExportStyle};
+use compdb::{output, CompileCommand};
+use config::Config;
+use edit::{FlagEdit, FlagEditor, FlagPattern};
+use matcher::{Matcher, Syntax};
+use rules::{Filter, FilterRule, PathBase, Reason};
+use stale::StaleCheck;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub struct CompileCommand {
-    pub command: String,
-    pub directory: String,
-    pub file: String,
-}
-
 #[derive(Parser)]
 #[command(name = "compdbfilter")]
+#[command(group = ArgGroup::new("selection").multiple(true))]
 #[command(about = "Filter compile_commands.json by regex patterns")]
+#[command(after_help = "Patterns are regexes matched against the file, or FIELD:REGEX for another field:\n  \
+    directory:REGEX, compiler:REGEX, output:REGEX, arg:REGEX, flag:NAME, flag:NAME=REGEX\n\
+    With --syntax glob or gitignore, file patterns are globs such as **/test/** or third_party/**/*.c")]
 struct Cli {
-    /// Path to compile_commands.json
+    /// Path to compile_commands.json, or `-` for stdin
     #[arg(default_value = "./compile_commands.json")]
     path: PathBuf,
 
-    /// Exclude files matching this regex (can be repeated)
-    #[arg(short, long, value_name = "REGEX")]
+    /// Write the result to this file instead of stdout, or `-` for stdout
+    #[arg(short, long, value_name = "PATH", conflicts_with = "in_place")]
+    output: Option<PathBuf>,
+
+    /// Edit PATH in place, keeping a backup
+    #[arg(long)]
+    in_place: bool,
+
+    /// Don't keep a backup when editing in place
+    #[arg(long, conflicts_with_all = ["backup_dir", "keep_backups"])]
+    no_backup: bool,
+
+    /// Keep backups in DIR instead of next to the database
+    #[arg(long, value_name = "DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// Remove all but the N most recent backups after creating one
+    #[arg(long, value_name = "N")]
+    keep_backups: Option<NonZeroUsize>,
+
+    /// List the backups of PATH, most recent first, with their entry counts and times
+    #[arg(long, conflicts_with_all = ["output", "in_place", "dry_run", "explain", "restore"])]
+    list_backups: bool,
+
+    /// Swap the Nth most recent backup (1 by default, see --list-backups) with PATH
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1",
+        conflicts_with_all = ["output", "in_place", "dry_run", "explain"])]
+    restore: Option<NonZeroUsize>,
+
+    /// List the files of the entries that would be removed, without writing anything
+    #[arg(long, conflicts_with_all = ["output", "in_place"])]
+    dry_run: bool,
+
+    /// Report which rule or pattern decides the entries for FILE, without writing anything
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["output", "in_place", "dry_run"])]
+    explain: Option<PathBuf>,
+
+    /// Ordered rule, `+ PATTERN` to keep or `- PATTERN` to drop matching entries; the
+    /// first matching rule wins over later rules and --exclude/--include (can be repeated)
+    #[arg(short, long, value_name = "RULE", allow_hyphen_values = true)]
+    rule: Vec<String>,
+
+    /// Exclude entries matching this pattern (can be repeated)
+    #[arg(short, long, value_name = "[FIELD:]REGEX")]
     exclude: Vec<String>,
 
-    /// Include files matching this regex even if excluded (can be repeated)
-    #[arg(short, long, value_name = "REGEX")]
+    /// Include entries matching this pattern even if excluded (can be repeated)
+    #[arg(short, long, value_name = "[FIELD:]REGEX")]
     include: Vec<String>,
+
+    /// Exclude entries matching the patterns in FILE, one per line (can be repeated)
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+
+    /// Include entries matching the patterns in FILE even if excluded (can be repeated)
+    #[arg(long, value_name = "FILE")]
+    include_from: Vec<PathBuf>,
+
+    /// Keep only entries for the files listed in FILE, one per line (can be repeated)
+    #[arg(long, value_name = "FILE", group = "selection")]
+    files_from: Vec<PathBuf>,
+
+    /// Keep only entries for files changed in a git revision range, e.g. origin/main...HEAD
+    #[arg(long, value_name = "RANGE", group = "selection")]
+    git_diff: Option<String>,
+
+    /// Keep only entries for files with staged changes
+    #[arg(long, group = "selection")]
+    git_staged: bool,
+
+    /// Keep only entries for files changed in the working tree, including untracked files
+    #[arg(long, group = "selection")]
+    git_worktree: bool,
+
+    /// Also keep entries whose dependency file (-MD, -MF) lists a selected file, such as
+    /// sources including a changed header
+    #[arg(long, requires = "selection")]
+    with_dependents: bool,
+
+    /// Drop entries whose file or directory no longer exists, reporting them
+    #[arg(long)]
+    drop_stale: bool,
+
+    /// With --drop-stale, also drop entries none of whose include directories exist
+    #[arg(long, requires = "drop_stale")]
+    stale_includes: bool,
+
+    /// Apply the rules and patterns of a profile from the configuration file; options
+    /// given on the command line take precedence (can be repeated)
+    #[arg(short, long, value_name = "NAME")]
+    profile: Vec<String>,
+
+    /// Configuration file with profiles [default: .compdb.toml in the database's
+    /// directory or its ancestors]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Syntax of file patterns given on the command line and in pattern files
+    #[arg(long, value_enum, default_value_t = Syntax::Regex)]
+    syntax: Syntax,
+
+    /// Path that file patterns are matched against [default: as-written]
+    #[arg(long, value_enum, value_name = "BASE")]
+    match_path: Option<MatchPath>,
+
+    /// Rewrite paths starting with FROM to start with TO (can be repeated, first match wins)
+    #[arg(long, value_name = "FROM=TO")]
+    map: Vec<PathMap>,
+
+    /// Normalize paths and resolve symbolic links of paths that exist
+    #[arg(long)]
+    canonicalize: bool,
+
+    /// Remove or translate GCC flags that clang-based tools reject
+    #[arg(long)]
+    gcc_compat: bool,
+
+    /// Extra flag rule for --gcc-compat, taking precedence over the built-in table (can be repeated)
+    #[arg(long, value_name = "RULE", allow_hyphen_values = true)]
+    compat_rule: Vec<Rule>,
+
+    /// Read extra flag rules for --gcc-compat from a file, one per line (can be repeated)
+    #[arg(long, value_name = "FILE")]
+    compat_rules: Vec<PathBuf>,
+
+    /// Remove matching arguments, and the value of options taking one: FLAG, FLAG* for a
+    /// prefix or /REGEX/ (can be repeated)
+    #[arg(long, value_name = "PATTERN", allow_hyphen_values = true)]
+    remove_flag: Vec<FlagPattern>,
+
+    /// Replace matching arguments, e.g. '-std=gnu89 => -std=gnu11' (can be repeated)
+    #[arg(long, value_name = "PATTERN => ARGS", allow_hyphen_values = true)]
+    replace_flag: Vec<String>,
+
+    /// Append arguments, shell-quoted (can be repeated)
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true)]
+    add_flag: Vec<String>,
+
+    /// Read flag edits from a file, one `add`, `remove` or `replace` per line, applied
+    /// before the other flag options (can be repeated)
+    #[arg(long, value_name = "FILE")]
+    flag_edits: Vec<PathBuf>,
+
+    /// Only edit flags of entries matching this pattern (can be repeated)
+    #[arg(long, value_name = "[FIELD:]PATTERN")]
+    edit_only: Vec<String>,
+
+    /// Rewrite paths under the project root to ${COMPDB_ROOT}, so the database can be shared
+    #[arg(long, conflicts_with = "instantiate")]
+    export: bool,
+
+    /// With --export, make paths relative instead of using ${COMPDB_ROOT}
+    #[arg(long, requires = "export")]
+    relative: bool,
+
+    /// Expand an exported database for the checkout at the project root
+    #[arg(long)]
+    instantiate: bool,
+
+    /// Project root for --export, --instantiate and --match-path root [default: the enclosing
+    /// checkout of the database]
+    #[arg(long, value_name = "DIR")]
+    root: Option<PathBuf>,
 }
 
 /// Find the next available backup path that doesn't exist.
 /// Returns paths like: file.bak, file.bak.1, file.bak.2, etc.
 pub fn find_backup_path(original: &Path) -> PathBuf {
-    let base = format!("{}.bak", original.display());
-    let base_path = PathBuf::from(&base);
+    Backups::new(original, None).next_path()
+}
 
-    if !base_path.exists() {
-        return base_path;
-    }
+/// The path file patterns are matched against, see `PathBase`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum MatchPath {
+    /// The file as written in the entry
+    AsWritten,
+    /// The normalized absolute path of the file
+    Absolute,
+    /// The file relative to the entry's directory
+    Directory,
+    /// The file relative to the project root
+    Root,
+}
 
-    let mut counter = 1;
-    loop {
-        let numbered = PathBuf::from(format!("{}.{}", base, counter));
-        if !numbered.exists() {
-            return numbered;
-        }
-        counter += 1;
-    }
+/// Where the filtered database is written.
+#[derive(Debug, PartialEq)]
+enum Destination {
+    InPlace,
+    File(PathBuf),
+    Stdout,
 }
 
-/// Filter compile commands based on exclude and include regex patterns.
+/// Filter compile commands based on exclude and include regex patterns for the file.
 /// A command is kept if:
 /// - It doesn't match any exclude pattern, OR
 /// - It matches an exclude pattern BUT also matches an include pattern (override)
@@ -57,17 +238,157 @@ pub fn filter_commands(
     exclude_patterns: &[Regex],
     include_patterns: &[Regex],
 ) -> Vec<CompileCommand> {
-    commands
-        .into_iter()
-        .filter(|cmd| {
-            let excluded = exclude_patterns.iter().any(|re| re.is_match(&cmd.file));
-            if !excluded {
-                return true;
-            }
-            // Check if included overrides exclusion
-            include_patterns.iter().any(|re| re.is_match(&cmd.file))
-        })
-        .collect()
+    let filter = Filter {
+        rules: Vec::new(),
+        exclude: exclude_patterns.iter().cloned().map(Matcher::File).collect(),
+        include: include_patterns.iter().cloned().map(Matcher::File).collect(),
+        base: PathBase::AsWritten,
+        selection: None,
+        stale: None,
+    };
+    commands.into_iter().filter(|cmd| filter.keep(cmd)).collect()
+}
+
+/// Check if an entry is for `file`, given as in the database or as a path relative to
+/// the current directory.
+fn is_entry_for(cmd: &CompileCommand, file: &Path) -> bool {
+    if Path::new(&cmd.file) == file {
+        return true;
+    }
+    let file = std::path::absolute(file).map(|f| paths::normalize(&f));
+    file.is_ok_and(|file| paths::normalize(&cmd.file_path()) == file)
+}
+
+/// Read a compilation database from `path`, or from stdin if it is `-`.
+fn read_database(path: &Path) -> Result<Vec<CompileCommand>, Box<dyn std::error::Error>> {
+    if path != Path::new("-") {
+        return database::load(path);
+    }
+    let mut content = String::new();
+    io::stdin().read_to_string(&mut content).map_err(|e| format!("failed to read stdin: {}", e))?;
+    database::parse(&content).map_err(|e| format!("invalid compilation database on stdin: {}", e).into())
+}
+
+/// Write a compilation database to stdout.
+fn write_stdout(commands: &[CompileCommand]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = Writer::new(BufWriter::new(io::stdout().lock()));
+    for cmd in commands {
+        writer.write(cmd)?;
+    }
+    let mut out = writer.finish()?;
+    writeln!(out)?;
+    Ok(out.flush()?)
+}
+
+/// The project root given with `--root`, or the checkout containing the database.
+fn project_root(root: Option<&Path>, database: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let root = match root {
+        Some(root) => root.to_path_buf(),
+        None => {
+            let database = std::path::absolute(database)?;
+            let start = database.parent().unwrap_or(Path::new("/"));
+            portable::find_project_root(start).ok_or("could not detect project root; pass --root")?
+        }
+    };
+    Ok(compdb::paths::normalize(&std::path::absolute(root)?))
+}
+
+/// List the backups of the database, or swap one back in with `--restore`.
+fn manage_backups(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.path == Path::new("-") {
+        return Err("stdin has no backups".into());
+    }
+    let series = Backups::new(&cli.path, cli.backup_dir.as_deref());
+    let backups = series.list()?;
+    let Some(n) = cli.restore else {
+        for (i, backup) in backups.iter().enumerate() {
+            let entries = match database::load(&backup.path) {
+                Ok(commands) => format!("{} entries", commands.len()),
+                Err(_) => "invalid".to_string(),
+            };
+            println!("{:>3}  {}  {:>12}  {}", i + 1, backup::format_time(backup.modified), entries, backup.path.display());
+        }
+        return Ok(());
+    };
+
+    let backup = backups
+        .get(n.get() - 1)
+        .ok_or_else(|| format!("{} has {} backups, not {}", cli.path.display(), backups.len(), n))?;
+    // Don't put a broken database in place
+    let restored = database::load(&backup.path)?;
+    let _lock = output::lock(&cli.path)?;
+    let saved = series.restore(&cli.path, &backup.path)?;
+    eprintln!(
+        "Restored {} from {} ({} entries); the replaced database is now in {}",
+        cli.path.display(),
+        backup.path.display(),
+        restored.len(),
+        saved.display()
+    );
+    Ok(())
+}
+
+/// The flag edits of `--flag-edits` files, then `--remove-flag`, `--replace-flag` and
+/// `--add-flag`.
+fn flag_editor(cli: &Cli) -> Result<FlagEditor, Box<dyn std::error::Error>> {
+    let mut edits = Vec::new();
+    for path in &cli.flag_edits {
+        edits.extend(edit::load_edits(path)?);
+    }
+    edits.extend(cli.remove_flag.iter().cloned().map(FlagEdit::Remove));
+    for replace in &cli.replace_flag {
+        edits.push(format!("replace {}", replace).parse()?);
+    }
+    for add in &cli.add_flag {
+        edits.push(format!("add {}", add).parse()?);
+    }
+    Ok(FlagEditor { edits })
+}
+
+/// Load the configuration file given with `--config`, or the one found from the
+/// database's directory, or the current directory for stdin.
+fn load_config(config: Option<&Path>, database: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+    let path = match config {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let database = std::path::absolute(database)?;
+            let start = database.parent().unwrap_or(Path::new("/"));
+            config::find_config(start).ok_or_else(|| format!("no {} found; pass --config", config::CONFIG_FILE))?
+        }
+    };
+    Ok(Config::load(&path)?)
+}
+
+/// The files selected with `--files-from` and the git options, if any were given.
+fn select_files(cli: &Cli) -> Result<Option<Selection>, Box<dyn std::error::Error>> {
+    let mut changes = Vec::new();
+    changes.extend(cli.git_diff.clone().map(GitChanges::Range));
+    if cli.git_staged {
+        changes.push(GitChanges::Staged);
+    }
+    if cli.git_worktree {
+        changes.push(GitChanges::Worktree);
+    }
+    if changes.is_empty() && cli.files_from.is_empty() {
+        return Ok(None);
+    }
+    let mut files = Vec::new();
+    for path in &cli.files_from {
+        files.extend(changes::read_file_list(path)?);
+    }
+    if !changes.is_empty() {
+        // Run git in the project root or the current directory, since build directories
+        // are often outside the checkout, and only then in the directory of the database
+        let mut dirs = vec![cli.root.clone().unwrap_or(std::env::current_dir()?)];
+        if let Some(dir) = std::path::absolute(&cli.path)?.parent() {
+            dirs.push(dir.to_path_buf());
+        }
+        let top = changes::git_toplevel(&dirs)?;
+        for change in &changes {
+            files.extend(changes::git_changed_files(&top, change)?);
+        }
+    }
+    Ok(Some(Selection::new(files, cli.with_dependents)))
 }
 
 /// Compile a list of regex pattern strings into Regex objects.
@@ -75,34 +396,188 @@ pub fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>, regex::Error>
     patterns.iter().map(|p| Regex::new(p)).collect()
 }
 
+/// Parse a list of `[FIELD:]PATTERN` patterns whose file patterns use `syntax`.
+pub fn parse_matchers(patterns: &[String], syntax: Syntax) -> Result<Vec<Matcher>, String> {
+    patterns.iter().map(|p| Matcher::parse(p, syntax)).collect()
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
-    // Compile regex patterns
-    let exclude_patterns = compile_patterns(&cli.exclude)?;
-    let include_patterns = compile_patterns(&cli.include)?;
+    if cli.list_backups || cli.restore.is_some() {
+        return manage_backups(&cli);
+    }
+
+    // Compile patterns. Command-line rules come first, so they win over profile rules,
+    // and command-line patterns last, so they win over earlier exclude and include patterns.
+    let mut filter = Filter::default();
+    for r in &cli.rule {
+        filter.rules.push(FilterRule::parse(r, cli.syntax)?);
+    }
+    let mut match_path = None;
+    if !cli.profile.is_empty() {
+        let config = load_config(cli.config.as_deref(), &cli.path)?;
+        for name in &cli.profile {
+            let profile = config.profile(name)?;
+            let syntax = profile.syntax.unwrap_or_default();
+            for r in &profile.rules {
+                filter.rules.push(FilterRule::parse(r, syntax).map_err(|e| format!("profile {}: {}", name, e))?);
+            }
+            filter.exclude.extend(parse_matchers(&profile.exclude, syntax).map_err(|e| format!("profile {}: {}", name, e))?);
+            filter.include.extend(parse_matchers(&profile.include, syntax).map_err(|e| format!("profile {}: {}", name, e))?);
+            match_path = profile.match_path.or(match_path);
+        }
+    }
+    for path in &cli.exclude_from {
+        let patterns = config::load_patterns(path)?;
+        filter.exclude.extend(parse_matchers(&patterns, cli.syntax).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    for path in &cli.include_from {
+        let patterns = config::load_patterns(path)?;
+        filter.include.extend(parse_matchers(&patterns, cli.syntax).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    filter.selection = select_files(&cli)?;
+    filter.stale = cli.drop_stale.then_some(StaleCheck { include_dirs: cli.stale_includes });
+    filter.exclude.extend(parse_matchers(&cli.exclude, cli.syntax)?);
+    filter.include.extend(parse_matchers(&cli.include, cli.syntax)?);
+    filter.base = match cli.match_path.or(match_path) {
+        None | Some(MatchPath::AsWritten) => PathBase::AsWritten,
+        Some(MatchPath::Absolute) => PathBase::Absolute,
+        Some(MatchPath::Directory) => PathBase::Directory,
+        Some(MatchPath::Root) => PathBase::Root(project_root(cli.root.as_deref(), &cli.path)?),
+    };
+
+    let editor = flag_editor(&cli)?;
+    let scope = parse_matchers(&cli.edit_only, cli.syntax)?;
+
+    let stdin = cli.path == Path::new("-");
+    let destination = match &cli.output {
+        _ if cli.dry_run || cli.explain.is_some() => None,
+        Some(path) if path == Path::new("-") => Some(Destination::Stdout),
+        Some(path) => Some(Destination::File(path.clone())),
+        None if stdin && cli.in_place => return Err("cannot edit stdin in place; use --output".into()),
+        None if cli.in_place => Some(Destination::InPlace),
+        None => Some(Destination::Stdout),
+    };
+
+    // Hold the lock until the filtered database is in place. Don't leave a lock
+    // file behind for a database that doesn't exist.
+    if !stdin && !cli.path.is_file() {
+        return Err(format!("{}: no such file", cli.path.display()).into());
+    }
+    let _lock = match &destination {
+        Some(Destination::InPlace) => Some(output::lock(&cli.path)?),
+        Some(Destination::File(path)) => Some(output::lock(path)?),
+        _ => None,
+    };
 
     // Read compile_commands.json
-    let content = fs::read_to_string(&cli.path)?;
-    let commands: Vec<CompileCommand> = serde_json::from_str(&content)?;
+    let mut commands = read_database(&cli.path)?;
     let original_count = commands.len();
 
-    // Create backup
-    let backup_path = find_backup_path(&cli.path);
-    fs::copy(&cli.path, &backup_path)?;
-    eprintln!("Backup created: {}", backup_path.display());
+    // Expand exported paths first, so mappings and patterns see local paths
+    if cli.instantiate {
+        let root = project_root(cli.root.as_deref(), &cli.path)?;
+        for cmd in &mut commands {
+            portable::instantiate(cmd, &root).map_err(|e| format!("{}: {}", cmd.file, e))?;
+        }
+    }
+
+    // Rewrite paths before matching, so patterns see the final paths
+    if !cli.map.is_empty() || cli.canonicalize {
+        let rewriter = PathRewriter { maps: cli.map, canonicalize: cli.canonicalize };
+        for cmd in &mut commands {
+            rewriter.rewrite(cmd).map_err(|e| format!("{}: {}", cmd.file, e))?;
+        }
+    }
+
+    // Rewrite flags; own rules extend the GCC table, so they imply it
+    if cli.gcc_compat || !cli.compat_rule.is_empty() || !cli.compat_rules.is_empty() {
+        let mut rules = cli.compat_rule;
+        for path in &cli.compat_rules {
+            rules.extend(compat::load_rules(path)?);
+        }
+        let compat = Compat::gcc_with(rules);
+        for cmd in &mut commands {
+            compat.rewrite(cmd).map_err(|e| format!("{}: {}", cmd.file, e))?;
+        }
+    }
+
+    // Edit flags after the compatibility rules, so edits can add flags they would remove
+    if !editor.edits.is_empty() {
+        for cmd in &mut commands {
+            // As for --exclude, the last matching pattern counts
+            let file = filter.base.file(cmd);
+            let last_match = scope.iter().rev().find(|m| m.matches_with(cmd, &file));
+            if scope.is_empty() || last_match.is_some_and(|m| !matches!(m, Matcher::Negated(_))) {
+                editor.rewrite(cmd).map_err(|e| format!("{}: {}", cmd.file, e))?;
+            }
+        }
+    }
+
+    if let Some(file) = &cli.explain {
+        let entries: Vec<_> = commands.iter().filter(|cmd| is_entry_for(cmd, file)).collect();
+        if entries.is_empty() {
+            return Err(format!("no entry for {}", file.display()).into());
+        }
+        for cmd in entries {
+            // Show the path patterns were matched against when it isn't the file as written
+            match filter.base.file(cmd) {
+                path if path == cmd.file => println!("{} ({}): {}", cmd.file, cmd.directory, filter.decide(cmd)),
+                path => println!("{} ({}) as {}: {}", cmd.file, cmd.directory, path, filter.decide(cmd)),
+            }
+        }
+        return Ok(());
+    }
 
     // Filter entries
-    let filtered = filter_commands(commands, &exclude_patterns, &include_patterns);
+    let (mut filtered, removed): (Vec<_>, Vec<_>) = commands.into_iter().partition(|cmd| {
+        let decision = filter.decide(cmd);
+        if let Reason::Stale(stale) = decision.reason {
+            eprintln!("Stale: {} ({}): {}", cmd.file, cmd.directory, stale);
+        }
+        decision.keep
+    });
     let filtered_count = filtered.len();
 
-    // Write filtered result
-    let output = serde_json::to_string_pretty(&filtered)?;
-    fs::write(&cli.path, output)?;
+    // Export last, so patterns and mappings apply to local paths
+    if cli.export {
+        let root = project_root(cli.root.as_deref(), &cli.path)?;
+        let style = if cli.relative { ExportStyle::Relative } else { ExportStyle::Placeholder };
+        for cmd in &mut filtered {
+            portable::export(cmd, &root, style).map_err(|e| format!("{}: {}", cmd.file, e))?;
+        }
+    }
+
+    // Write filtered result, replacing the destination atomically
+    match &destination {
+        None => {
+            for cmd in &removed {
+                println!("{}", cmd.file);
+            }
+        }
+        Some(Destination::InPlace) => {
+            // Back up only once nothing can fail before the database is replaced
+            if !cli.no_backup {
+                let backups = Backups::new(&cli.path, cli.backup_dir.as_deref());
+                let backup_path = backups.create(&cli.path)?;
+                eprintln!("Backup created: {}", backup_path.display());
+                if let Some(keep) = cli.keep_backups {
+                    for removed in backups.prune(keep.get())? {
+                        eprintln!("Backup removed: {}", removed.display());
+                    }
+                }
+            }
+            database::save(&cli.path, &filtered)?
+        }
+        Some(Destination::File(path)) => database::save(path, &filtered)?,
+        Some(Destination::Stdout) => write_stdout(&filtered)?,
+    }
 
     // Print statistics
     eprintln!(
-        "Filtered: {} -> {} entries ({} removed)",
+        "{}: {} -> {} entries ({} removed)",
+        if cli.dry_run { "Dry run" } else { "Filtered" },
         original_count,
         filtered_count,
         original_count - filtered_count
@@ -114,14 +589,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     use tempfile::TempDir;
 
     fn make_cmd(file: &str) -> CompileCommand {
-        CompileCommand {
-            command: format!("gcc -c {}", file),
-            directory: "/build".to_string(),
-            file: file.to_string(),
-        }
+        CompileCommand::with_command("/build", file, format!("gcc -c {}", file))
     }
 
     // Tests for compile_patterns
@@ -447,7 +919,7 @@ mod tests {
             let cmd: CompileCommand = serde_json::from_str(json).unwrap();
             assert_eq!(cmd.file, "foo.c");
             assert_eq!(cmd.directory, "/home/build");
-            assert_eq!(cmd.command, "gcc -c foo.c");
+            assert_eq!(cmd.command.as_deref(), Some("gcc -c foo.c"));
         }
 
         #[test]
@@ -472,11 +944,7 @@ mod tests {
 
         #[test]
         fn handles_unicode_in_paths() {
-            let cmd = CompileCommand {
-                command: "gcc -c файл.c".to_string(),
-                directory: "/сборка".to_string(),
-                file: "файл.c".to_string(),
-            };
+            let cmd = CompileCommand::with_command("/сборка", "файл.c", "gcc -c файл.c");
             let json = serde_json::to_string(&cmd).unwrap();
             let deserialized: CompileCommand = serde_json::from_str(&json).unwrap();
             assert_eq!(cmd, deserialized);
@@ -484,11 +952,7 @@ mod tests {
 
         #[test]
         fn handles_special_chars_in_command() {
-            let cmd = CompileCommand {
-                command: r#"gcc -DVERSION=\"1.0\" -c file.c"#.to_string(),
-                directory: "/build".to_string(),
-                file: "file.c".to_string(),
-            };
+            let cmd = CompileCommand::with_command("/build", "file.c", r#"gcc -DVERSION=\"1.0\" -c file.c"#);
             let json = serde_json::to_string(&cmd).unwrap();
             let deserialized: CompileCommand = serde_json::from_str(&json).unwrap();
             assert_eq!(cmd, deserialized);
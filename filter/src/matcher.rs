@@ -0,0 +1,327 @@
+use compdb::{flags, CompileCommand};
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+/// A criterion selecting compile commands, written `[FIELD:]PATTERN`.
+///
+/// - `file:REGEX` (the default) matches the source file
+/// - `directory:REGEX` matches the working directory
+/// - `compiler:REGEX` matches the compiler, the first argument
+/// - `output:REGEX` matches the output file, from `output` or `-o`
+/// - `arg:REGEX` matches any argument after the compiler
+/// - `flag:NAME` matches if the flag is present, alone or with a value
+/// - `flag:NAME=REGEX` matches if the flag's value matches, e.g. `flag:-D=^UNIT_TEST`
+///
+/// File patterns can also be written as globs or gitignore-style patterns, see `Syntax`.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    File(Regex),
+    /// A file glob or gitignore-style pattern, kept as written for display.
+    Path { pattern: String, regex: Regex },
+    /// A gitignore-style `!PATTERN`. It matches what `PATTERN` matches, and undoes
+    /// earlier matching patterns of the same list.
+    Negated(Box<Matcher>),
+    Directory(Regex),
+    Compiler(Regex),
+    Output(Regex),
+    Arg(Regex),
+    Flag { name: String, value: Option<Regex> },
+}
+
+/// How file patterns are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Syntax {
+    /// Regular expressions, matching anywhere in the path unless anchored
+    #[default]
+    Regex,
+    /// Shell globs matching the whole path: `*` and `?` don't match `/`, `**` matches
+    /// any number of directories
+    Glob,
+    /// Globs as in `.gitignore`: a pattern without `/` matches in any directory, a
+    /// trailing `/` matches only directories, and `!PATTERN` undoes earlier patterns
+    Gitignore,
+}
+
+/// Translate a glob to a regex, without anchors.
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let starts_component = i == 0 || chars[i - 1] == '/';
+                if starts_component && chars.get(i + 2) == Some(&'/') {
+                    // `**/` also matches no directory at all
+                    re.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    re.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                let Some(len) = chars[i + 1..].iter().skip(1).position(|&c| c == ']') else {
+                    re.push_str("\\[");
+                    i += 1;
+                    continue;
+                };
+                let class: String = chars[i + 1..i + 2 + len].iter().collect();
+                let class = class.strip_prefix('!').map_or(class.clone(), |rest| format!("^{}", rest));
+                re.push('[');
+                for c in class.chars() {
+                    if matches!(c, '\\' | '[' | '&' | '~') {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+                i += len + 3;
+                continue;
+            }
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        i += 1;
+    }
+    re
+}
+
+/// Translate a gitignore-style pattern to an anchored regex. Returns whether it is negated.
+fn gitignore_to_regex(pattern: &str) -> (String, bool) {
+    let (pattern, negated) = match pattern.strip_prefix('!') {
+        Some(rest) => (rest, true),
+        None => (pattern, false),
+    };
+    let (pattern, dir_only) = match pattern.strip_suffix('/') {
+        Some(rest) => (rest, true),
+        None => (pattern, false),
+    };
+    // Patterns with a slash are relative to the base, others match in any directory
+    let (prefix, pattern) = match pattern.strip_prefix('/') {
+        Some(rest) => ("", rest),
+        None if pattern.contains('/') => ("", pattern),
+        None => ("(?:.*/)?", pattern),
+    };
+    // The matched paths are files, so a directory pattern matches what is below it
+    let suffix = if dir_only { "/.*" } else { "(?:/.*)?" };
+    (format!("^{}{}{}$", prefix, glob_to_regex(pattern), suffix), negated)
+}
+
+impl Matcher {
+    /// Parse a `[FIELD:]PATTERN` criterion whose file patterns use `syntax`. Patterns
+    /// for the other fields are always regexes.
+    pub fn parse(s: &str, syntax: Syntax) -> Result<Self, String> {
+        let regex = |pattern: &str| Regex::new(pattern).map_err(|e| format!("invalid pattern {:?}: {}", s, e));
+        let file = |pattern: &str| -> Result<Matcher, String> {
+            let (translated, negated) = match syntax {
+                Syntax::Regex => return Ok(Matcher::File(regex(pattern)?)),
+                Syntax::Glob => (format!("^{}$", glob_to_regex(pattern)), false),
+                Syntax::Gitignore => gitignore_to_regex(pattern),
+            };
+            let pattern = if negated { &pattern[1..] } else { pattern };
+            let matcher = Matcher::Path { pattern: pattern.to_string(), regex: regex(&translated)? };
+            Ok(if negated { Matcher::Negated(Box::new(matcher)) } else { matcher })
+        };
+        let Some((field, pattern)) = s.split_once(':') else {
+            return file(s);
+        };
+        match field {
+            "file" => file(pattern),
+            "directory" => Ok(Matcher::Directory(regex(pattern)?)),
+            "compiler" => Ok(Matcher::Compiler(regex(pattern)?)),
+            "output" => Ok(Matcher::Output(regex(pattern)?)),
+            "arg" => Ok(Matcher::Arg(regex(pattern)?)),
+            "flag" => {
+                let (name, value) = match pattern.split_once('=') {
+                    Some((name, value)) => (name, Some(regex(value)?)),
+                    None => (pattern, None),
+                };
+                if !name.starts_with('-') {
+                    return Err(format!("invalid pattern {:?}: flag names start with '-'", s));
+                }
+                Ok(Matcher::Flag { name: name.to_string(), value })
+            }
+            // Not a known field, so the colon is part of a file pattern
+            _ => file(s),
+        }
+    }
+}
+
+impl FromStr for Matcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Matcher::parse(s, Syntax::Regex)
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Matcher::File(re) => write!(f, "{}", re),
+            Matcher::Path { pattern, .. } => write!(f, "{}", pattern),
+            Matcher::Negated(matcher) => write!(f, "!{}", matcher),
+            Matcher::Directory(re) => write!(f, "directory:{}", re),
+            Matcher::Compiler(re) => write!(f, "compiler:{}", re),
+            Matcher::Output(re) => write!(f, "output:{}", re),
+            Matcher::Arg(re) => write!(f, "arg:{}", re),
+            Matcher::Flag { name, value: None } => write!(f, "flag:{}", name),
+            Matcher::Flag { name, value: Some(re) } => write!(f, "flag:{}={}", name, re),
+        }
+    }
+}
+
+/// Values given to flag `name` in `args`: the next argument for a flag that takes one,
+/// or the text joined to it. Text is joined directly only to single-letter flags such as
+/// `-DNAME` and flags that take a value, and with `=` to any other flag, so `-DX` is not
+/// mistaken for `-DXY`.
+fn flag_values<'a>(name: &'a str, args: &'a [String]) -> impl Iterator<Item = &'a str> + 'a {
+    let joins_directly = name.len() == 2 || flags::takes_value(name);
+    args.iter().enumerate().filter_map(move |(i, arg)| {
+        if arg == name {
+            return Some(if flags::takes_value(arg) { args.get(i + 1).map_or("", String::as_str) } else { "" });
+        }
+        let rest = arg.strip_prefix(name)?;
+        match rest.strip_prefix('=') {
+            Some(value) => Some(value),
+            None if joins_directly => Some(rest),
+            None => None,
+        }
+    })
+}
+
+impl Matcher {
+    /// Check if a compile command matches. Entries whose command line cannot be split
+    /// match no argument criteria.
+    pub fn matches(&self, cmd: &CompileCommand) -> bool {
+        self.matches_with(cmd, &cmd.file)
+    }
+
+    /// Check if a compile command matches, with file patterns matched against `file`
+    /// instead of the entry's file, e.g. its path relative to the project root.
+    pub fn matches_with(&self, cmd: &CompileCommand, file: &str) -> bool {
+        match self {
+            Matcher::File(re) | Matcher::Path { regex: re, .. } => re.is_match(file),
+            Matcher::Negated(matcher) => matcher.matches_with(cmd, file),
+            Matcher::Directory(re) => re.is_match(&cmd.directory),
+            Matcher::Compiler(re) => cmd.args().is_ok_and(|args| args.first().is_some_and(|c| re.is_match(c))),
+            Matcher::Output(re) => match &cmd.output {
+                Some(output) => re.is_match(output),
+                None => cmd.args().is_ok_and(|args| {
+                    flag_values("-o", args.get(1..).unwrap_or_default()).any(|o| re.is_match(o))
+                }),
+            },
+            Matcher::Arg(re) => cmd.args().is_ok_and(|args| args.iter().skip(1).any(|arg| re.is_match(arg))),
+            Matcher::Flag { name, value } => cmd.args().is_ok_and(|args| {
+                let mut values = flag_values(name, args.get(1..).unwrap_or_default());
+                match value {
+                    Some(re) => values.any(|v| re.is_match(v)),
+                    None => values.next().is_some(),
+                }
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(directory: &str, file: &str, command: &str) -> CompileCommand {
+        CompileCommand::with_command(directory, file, command)
+    }
+
+    fn matches(pattern: &str, cmd: &CompileCommand) -> bool {
+        pattern.parse::<Matcher>().unwrap().matches(cmd)
+    }
+
+    #[test]
+    fn plain_patterns_match_file() {
+        let c = cmd("/build", "src/a.c", "gcc -c src/a.c");
+        assert!(matches("^src/", &c));
+        assert!(matches("file:a\\.c$", &c));
+        assert!(!matches("^/build", &c));
+        // An unknown field name is part of the regex
+        assert!(matches("(?:src)/a", &c));
+    }
+
+    #[test]
+    fn matches_directory_compiler_and_output() {
+        let mut c = cmd("/build/host", "a.c", "/opt/cross/bin/aarch64-linux-gnu-gcc -c a.c -o host/a.o");
+        assert!(matches("directory:/host$", &c));
+        assert!(matches("compiler:aarch64", &c));
+        assert!(!matches("compiler:^gcc$", &c));
+        assert!(matches("output:^host/", &c));
+
+        c.output = Some("target/a.o".to_string());
+        assert!(!matches("output:^host/", &c));
+        assert!(matches("output:^target/", &c));
+    }
+
+    #[test]
+    fn matches_flags_with_and_without_values() {
+        let c = cmd("/b", "a.c", "gcc -DUNIT_TEST -D MODE=2 -std=c11 -isystem /opt/inc -c a.c");
+        assert!(matches("flag:-DUNIT_TEST", &c));
+        assert!(matches("flag:-D=^UNIT_TEST$", &c));
+        assert!(matches("flag:-D=^MODE=2$", &c));
+        assert!(matches("flag:-std=c1[17]", &c));
+        assert!(matches("flag:-isystem=^/opt", &c));
+        assert!(!matches("flag:-DRELEASE", &c));
+        assert!(!matches("flag:-DUNIT", &c));
+        assert!(!matches("flag:-std=c\\+\\+", &c));
+    }
+
+    #[test]
+    fn matches_any_argument() {
+        let c = cmd("/b", "a.c", "gcc -fsanitize=address -c a.c");
+        assert!(matches("arg:^-fsanitize=", &c));
+        assert!(!matches("arg:^gcc$", &c));
+    }
+
+    #[test]
+    fn matches_globs() {
+        let glob = |pattern: &str, file: &str| Matcher::parse(pattern, Syntax::Glob).unwrap().matches(&cmd("/b", file, "cc"));
+        assert!(glob("**/test/**", "test/a.c"));
+        assert!(glob("**/test/**", "src/test/unit/a.c"));
+        assert!(!glob("**/test/**", "src/testing/a.c"));
+        assert!(glob("third_party/**/*.c", "third_party/zlib/inflate.c"));
+        assert!(glob("third_party/**/*.c", "third_party/a.c"));
+        assert!(!glob("third_party/**/*.c", "third_party/zlib/inflate.h"));
+        assert!(!glob("src/*.c", "src/sub/a.c"));
+        assert!(glob("arch/[ax]*/?.c", "arch/x86/a.c"));
+        assert!(!glob("arch/[!ax]*/?.c", "arch/x86/a.c"));
+        assert!(glob("a+b(1).c", "a+b(1).c"));
+        assert_eq!(Matcher::parse("file:**/*.c", Syntax::Glob).unwrap().to_string(), "**/*.c");
+        // Other fields stay regexes
+        assert!(Matcher::parse("directory:^/b$", Syntax::Glob).unwrap().matches(&cmd("/b", "a.c", "cc")));
+    }
+
+    #[test]
+    fn matches_gitignore_patterns() {
+        let ignore = |pattern: &str, file: &str| {
+            Matcher::parse(pattern, Syntax::Gitignore).unwrap().matches(&cmd("/b", file, "cc"))
+        };
+        assert!(ignore("test", "src/test/a.c"));
+        assert!(ignore("*.pb.cc", "proto/x.pb.cc"));
+        assert!(ignore("/vendor", "vendor/lib.c"));
+        assert!(!ignore("/vendor", "src/vendor/lib.c"));
+        assert!(ignore("drivers/gpu/", "drivers/gpu/drm.c"));
+        assert!(!ignore("gpu/", "src/gpu"));
+        assert!(!ignore("src/test", "lib/src/test/a.c"));
+
+        let negated = Matcher::parse("!drivers/gpu/", Syntax::Gitignore).unwrap();
+        assert!(matches!(negated, Matcher::Negated(_)));
+        assert_eq!(negated.to_string(), "!drivers/gpu/");
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!("directory:[".parse::<Matcher>().unwrap_err().contains("invalid pattern"));
+        assert!("flag:DEBUG".parse::<Matcher>().is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::changes::Selection;
+use crate::matcher::{Matcher, Syntax};
+use crate::stale::{Stale, StaleCheck};
+use compdb::{paths, CompileCommand};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What a matching rule does with an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Include,
+    Exclude,
+}
+
+/// An ordered filter rule, written `+ PATTERN` to keep or `- PATTERN` to drop the
+/// entries matching `PATTERN`.
+#[derive(Debug, Clone)]
+pub struct FilterRule {
+    pub action: Action,
+    pub matcher: Matcher,
+}
+
+impl FilterRule {
+    /// Parse a rule whose file patterns use `syntax`. Negated patterns are rejected, as
+    /// the sign already says what a rule does.
+    pub fn parse(s: &str, syntax: Syntax) -> Result<Self, String> {
+        let (action, pattern) = match s.chars().next() {
+            Some('+') => (Action::Include, &s[1..]),
+            Some('-') => (Action::Exclude, &s[1..]),
+            _ => return Err(format!("invalid rule {:?}, expected '+ PATTERN' or '- PATTERN'", s)),
+        };
+        let pattern = pattern.strip_prefix(' ').unwrap_or(pattern);
+        match Matcher::parse(pattern, syntax)? {
+            Matcher::Negated(_) => Err(format!("invalid rule {:?}: use '+' instead of negating the pattern", s)),
+            matcher => Ok(FilterRule { action, matcher }),
+        }
+    }
+}
+
+impl FromStr for FilterRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FilterRule::parse(s, Syntax::Regex)
+    }
+}
+
+impl fmt::Display for FilterRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.action {
+            Action::Include => '+',
+            Action::Exclude => '-',
+        };
+        write!(f, "{} {}", sign, self.matcher)
+    }
+}
+
+/// Why an entry was kept or dropped.
+#[derive(Debug, Clone, Copy)]
+pub enum Reason<'a> {
+    /// The first matching ordered rule, with its 1-based position.
+    Rule(usize, &'a FilterRule),
+    /// An `--exclude` pattern matched and no `--include` pattern did.
+    Excluded(&'a Matcher),
+    /// The last matching `--exclude` pattern is negated.
+    Negated(&'a Matcher),
+    /// An `--include` pattern overrode an `--exclude` pattern.
+    Included { exclude: &'a Matcher, include: &'a Matcher },
+    /// Nothing matched, so the entry is kept.
+    NoMatch,
+    /// The entry isn't for a listed or changed file.
+    NotSelected,
+    /// The entry refers to files that no longer exist.
+    Stale(Stale),
+}
+
+/// Whether an entry is kept, and why.
+#[derive(Debug, Clone, Copy)]
+pub struct Decision<'a> {
+    pub keep: bool,
+    pub reason: Reason<'a>,
+}
+
+impl fmt::Display for Decision<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.keep { "kept" } else { "excluded" };
+        match self.reason {
+            Reason::Rule(n, rule) => write!(f, "{} by rule {}: {}", verdict, n, rule),
+            Reason::Excluded(exclude) | Reason::Negated(exclude) => write!(f, "{} by --exclude {}", verdict, exclude),
+            Reason::Included { exclude, include } => {
+                write!(f, "{} by --include {} (overrides --exclude {})", verdict, include, exclude)
+            }
+            Reason::NoMatch => write!(f, "{}: no rule or pattern matched", verdict),
+            Reason::NotSelected => write!(f, "{}: not a listed or changed file", verdict),
+            Reason::Stale(stale) => write!(f, "{} as stale: {}", verdict, stale),
+        }
+    }
+}
+
+/// The path file patterns are matched against.
+#[derive(Debug, Clone, Default)]
+pub enum PathBase {
+    /// The `file` field as written in the entry.
+    #[default]
+    AsWritten,
+    /// The normalized absolute path of the file.
+    Absolute,
+    /// The file relative to the entry's `directory`, if it is inside it.
+    Directory,
+    /// The file relative to the project root, if it is inside it.
+    Root(PathBuf),
+}
+
+impl PathBase {
+    /// The path of an entry's file for matching. Except as written, the file is resolved
+    /// against the entry's directory and normalized, so `./a.c`, `../src/a.c` and
+    /// `/src/a.c` give the same path. Files outside the base are given as absolute paths.
+    pub fn file<'a>(&self, cmd: &'a CompileCommand) -> Cow<'a, str> {
+        let file = paths::normalize(&cmd.file_path());
+        let base = match self {
+            PathBase::AsWritten => return Cow::Borrowed(&cmd.file),
+            PathBase::Absolute => None,
+            PathBase::Directory => Some(paths::normalize(Path::new(&cmd.directory))),
+            PathBase::Root(root) => Some(paths::normalize(root)),
+        };
+        let file = match base.as_deref().map(|base| file.strip_prefix(base)) {
+            Some(Ok(rest)) if file.is_absolute() && !rest.as_os_str().is_empty() => rest,
+            _ => &file,
+        };
+        Cow::Owned(file.to_string_lossy().into_owned())
+    }
+}
+
+/// Decides which entries are kept. Entries a selection doesn't select are dropped,
+/// and so are stale entries if they are checked.
+/// Ordered rules are tried next and the first matching one decides. Entries no rule
+/// matches fall back to `--exclude` and `--include`:
+/// excluded if an exclude pattern matches, unless an include pattern matches too. Within
+/// each list the last matching pattern counts, so a negated pattern undoes earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub rules: Vec<FilterRule>,
+    pub exclude: Vec<Matcher>,
+    pub include: Vec<Matcher>,
+    pub base: PathBase,
+    pub selection: Option<Selection>,
+    pub stale: Option<StaleCheck>,
+}
+
+impl Filter {
+    pub fn decide(&self, cmd: &CompileCommand) -> Decision<'_> {
+        if self.selection.as_ref().is_some_and(|selection| !selection.selects(cmd)) {
+            return Decision { keep: false, reason: Reason::NotSelected };
+        }
+        if let Some(stale) = self.stale.and_then(|check| check.check(cmd)) {
+            return Decision { keep: false, reason: Reason::Stale(stale) };
+        }
+        let file = self.base.file(cmd);
+        let matches = |m: &&Matcher| m.matches_with(cmd, &file);
+        if let Some((i, rule)) = self.rules.iter().enumerate().find(|(_, rule)| matches(&&rule.matcher)) {
+            let keep = rule.action == Action::Include;
+            return Decision { keep, reason: Reason::Rule(i + 1, rule) };
+        }
+        let exclude = match self.exclude.iter().rev().find(matches) {
+            None => return Decision { keep: true, reason: Reason::NoMatch },
+            Some(exclude @ Matcher::Negated(_)) => return Decision { keep: true, reason: Reason::Negated(exclude) },
+            Some(exclude) => exclude,
+        };
+        match self.include.iter().rev().find(matches).filter(|m| !matches!(m, Matcher::Negated(_))) {
+            Some(include) => Decision { keep: true, reason: Reason::Included { exclude, include } },
+            None => Decision { keep: false, reason: Reason::Excluded(exclude) },
+        }
+    }
+
+    pub fn keep(&self, cmd: &CompileCommand) -> bool {
+        self.decide(cmd).keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_cmd(file: &str) -> CompileCommand {
+        CompileCommand::with_command("/build", file, format!("gcc -c {}", file))
+    }
+
+    fn filter(rules: &[&str], exclude: &[&str], include: &[&str]) -> Filter {
+        Filter {
+            rules: rules.iter().map(|r| r.parse().unwrap()).collect(),
+            exclude: exclude.iter().map(|p| p.parse().unwrap()).collect(),
+            include: include.iter().map(|p| p.parse().unwrap()).collect(),
+            ..Filter::default()
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule: FilterRule = "- ^drivers/".parse().unwrap();
+        assert_eq!(rule.action, Action::Exclude);
+        assert_eq!(rule.to_string(), "- ^drivers/");
+        let rule: FilterRule = "+flag:-DKEEP".parse().unwrap();
+        assert_eq!(rule.action, Action::Include);
+        assert_eq!(rule.to_string(), "+ flag:-DKEEP");
+        assert!("^drivers/".parse::<FilterRule>().is_err());
+        assert!("- [".parse::<FilterRule>().is_err());
+        assert!(FilterRule::parse("- !tests/", Syntax::Gitignore).is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let f = filter(&["- ^drivers/gpu/nouveau/", "+ ^drivers/gpu/", "- ^drivers/"], &[], &[]);
+        assert!(!f.keep(&make_cmd("drivers/net/e1000.c")));
+        assert!(f.keep(&make_cmd("drivers/gpu/drm.c")));
+        assert!(!f.keep(&make_cmd("drivers/gpu/nouveau/nv50.c")));
+        assert!(f.keep(&make_cmd("kernel/fork.c")));
+    }
+
+    #[test]
+    fn rules_take_precedence_over_patterns() {
+        let f = filter(&["+ ^tests/keep"], &["^tests/"], &[]);
+        assert!(f.keep(&make_cmd("tests/keep.c")));
+        assert!(!f.keep(&make_cmd("tests/other.c")));
+    }
+
+    #[test]
+    fn last_matching_pattern_counts_for_negation() {
+        let parse = |patterns: &[&str]| patterns.iter().map(|p| Matcher::parse(p, Syntax::Gitignore).unwrap()).collect();
+        let f = Filter { exclude: parse(&["drivers/", "!drivers/gpu/", "nouveau/"]), ..Filter::default() };
+        assert!(!f.keep(&make_cmd("drivers/net/e1000.c")));
+        assert!(f.keep(&make_cmd("drivers/gpu/drm.c")));
+        assert!(!f.keep(&make_cmd("drivers/gpu/nouveau/nv50.c")));
+        assert_eq!(f.decide(&make_cmd("drivers/gpu/drm.c")).to_string(), "kept by --exclude !drivers/gpu/");
+    }
+
+    #[test]
+    fn matches_paths_relative_to_base() {
+        let cmd = CompileCommand::with_command("/src/proj/build", "/src/proj/lib/a.c", "cc -c /src/proj/lib/a.c");
+        let rel = CompileCommand::with_command("/src/proj/build", "../lib/b.c", "cc -c ../lib/b.c");
+        assert_eq!(PathBase::AsWritten.file(&cmd), "/src/proj/lib/a.c");
+        assert_eq!(PathBase::Root("/src/proj".into()).file(&cmd), "lib/a.c");
+        assert_eq!(PathBase::Root("/src/proj".into()).file(&rel), "lib/b.c");
+        assert_eq!(PathBase::Root("/other".into()).file(&rel), "/src/proj/lib/b.c");
+        assert_eq!(PathBase::Directory.file(&cmd), "/src/proj/lib/a.c");
+        assert_eq!(PathBase::Directory.file(&rel), "/src/proj/lib/b.c");
+        assert_eq!(PathBase::Absolute.file(&rel), "/src/proj/lib/b.c");
+
+        let f = Filter {
+            exclude: vec![Matcher::parse("lib/**", Syntax::Glob).unwrap()],
+            base: PathBase::Root("/src/proj".into()),
+            ..Filter::default()
+        };
+        assert!(!f.keep(&cmd));
+        assert!(!f.keep(&rel));
+    }
+
+    #[test]
+    fn resolves_and_normalizes_files() {
+        let written = ["/src/proj/build/gen/./x.c", "gen/x.c", "./gen/../gen/x.c", "../build/gen/x.c"];
+        for file in written {
+            let cmd = CompileCommand::with_command("/src/proj/build/", file, "cc -c x.c");
+            assert_eq!(PathBase::Absolute.file(&cmd), "/src/proj/build/gen/x.c", "{}", file);
+            assert_eq!(PathBase::Directory.file(&cmd), "gen/x.c", "{}", file);
+            assert_eq!(PathBase::Root("/src/proj/./".into()).file(&cmd), "build/gen/x.c", "{}", file);
+        }
+    }
+
+    #[test]
+    fn explains_decisions() {
+        let f = filter(&["- ^vendor/"], &["^tests/"], &["integration"]);
+        assert_eq!(f.decide(&make_cmd("vendor/z.c")).to_string(), "excluded by rule 1: - ^vendor/");
+        assert_eq!(f.decide(&make_cmd("tests/unit.c")).to_string(), "excluded by --exclude ^tests/");
+        assert_eq!(
+            f.decide(&make_cmd("tests/integration.c")).to_string(),
+            "kept by --include integration (overrides --exclude ^tests/)"
+        );
+        assert_eq!(f.decide(&make_cmd("src/a.c")).to_string(), "kept: no rule or pattern matched");
+    }
+}
@@ -0,0 +1,97 @@
+use compdb::CompileCommand;
+use std::fmt;
+use std::path::Path;
+
+/// Options whose value is an include directory.
+const INCLUDE_OPTIONS: &[&str] = &["-I", "-isystem", "-iquote", "-idirafter"];
+
+/// Why an entry is stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stale {
+    Directory,
+    File,
+    IncludeDirs,
+}
+
+impl fmt::Display for Stale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stale::Directory => write!(f, "directory does not exist"),
+            Stale::File => write!(f, "file does not exist"),
+            Stale::IncludeDirs => write!(f, "no include directory exists"),
+        }
+    }
+}
+
+/// The include directories of compiler arguments, without the compiler.
+fn include_dirs(args: &[String]) -> Vec<&str> {
+    let mut dirs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if INCLUDE_OPTIONS.contains(&arg.as_str()) {
+            dirs.extend(args.next().map(String::as_str));
+        } else if let Some(dir) = INCLUDE_OPTIONS.iter().find_map(|option| arg.strip_prefix(option)) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Checks entries against the filesystem, for databases that outlived their checkout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StaleCheck {
+    /// Also treat entries as stale when none of their include directories exist.
+    pub include_dirs: bool,
+}
+
+impl StaleCheck {
+    pub fn check(&self, cmd: &CompileCommand) -> Option<Stale> {
+        let directory = Path::new(&cmd.directory);
+        if !directory.is_dir() {
+            return Some(Stale::Directory);
+        }
+        if !cmd.file_path().exists() {
+            return Some(Stale::File);
+        }
+        if self.include_dirs {
+            let args = cmd.args().ok()?;
+            let dirs = include_dirs(args.get(1..).unwrap_or_default());
+            if !dirs.is_empty() && !dirs.iter().any(|dir| directory.join(dir).is_dir()) {
+                return Some(Stale::IncludeDirs);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn finds_include_dirs() {
+        let args: Vec<String> =
+            ["-Iinc", "-I", "gen", "-isystem/opt/x", "-DX", "-c", "a.c"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(include_dirs(&args), vec!["inc", "gen", "/opt/x"]);
+    }
+
+    #[test]
+    fn detects_stale_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("inc")).unwrap();
+        fs::write(dir.join("a.c"), "").unwrap();
+        let dir_str = dir.to_str().unwrap();
+        let check = StaleCheck { include_dirs: true };
+
+        let cmd = |directory: &str, file: &str, command: &str| CompileCommand::with_command(directory, file, command);
+        assert_eq!(check.check(&cmd(dir_str, "a.c", "cc -Igone -Iinc -c a.c")), None);
+        assert_eq!(check.check(&cmd(dir_str, "a.c", "cc -c a.c")), None);
+        assert_eq!(check.check(&cmd(dir_str, "b.c", "cc -c b.c")), Some(Stale::File));
+        assert_eq!(check.check(&cmd(&format!("{}/out", dir_str), "../a.c", "cc -c ../a.c")), Some(Stale::Directory));
+        assert_eq!(check.check(&cmd(dir_str, "a.c", "cc -I gone -c a.c")), Some(Stale::IncludeDirs));
+        assert_eq!(StaleCheck::default().check(&cmd(dir_str, "a.c", "cc -I gone -c a.c")), None);
+    }
+}
@@ -43,15 +43,18 @@ fn test_cli_default_path() {
 
     let commands = sample_compile_db();
     fs::write(&db_path, serde_json::to_string_pretty(&commands).unwrap()).unwrap();
+    let original = fs::read_to_string(&db_path).unwrap();
 
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
         .assert()
         .success()
+        .stdout(predicate::str::contains("vendor/lib.c"))
         .stderr(predicate::str::contains("Filtered: 4 -> 4 entries"));
 
-    // Verify backup was created
-    assert!(temp_dir.path().join("compile_commands.json.bak").exists());
+    // Without --in-place the database is left as it is
+    assert_eq!(fs::read_to_string(&db_path).unwrap(), original);
+    assert!(!temp_dir.path().join("compile_commands.json.bak").exists());
 }
 
 #[test]
@@ -63,6 +66,7 @@ fn test_cli_custom_path() {
     fs::write(&db_path, serde_json::to_string_pretty(&commands).unwrap()).unwrap();
 
     cargo_bin_cmd!("compdb-filter")
+        .arg("--in-place")
         .arg(db_path.to_str().unwrap())
         .assert()
         .success()
@@ -82,6 +86,7 @@ fn test_cli_exclude_pattern() {
 
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .arg("-e")
         .arg("^tests/")
         .assert()
@@ -105,6 +110,7 @@ fn test_cli_multiple_exclude_patterns() {
 
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .arg("-e")
         .arg("^tests/")
         .arg("-e")
@@ -136,6 +142,7 @@ fn test_cli_include_pattern() {
 
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .arg("-e")
         .arg("^tests/")
         .arg("-i")
@@ -163,6 +170,7 @@ fn test_cli_backup_preservation() {
     // First run
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .assert()
         .success();
 
@@ -173,6 +181,7 @@ fn test_cli_backup_preservation() {
     // Second run - should create .bak.1
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .assert()
         .success();
 
@@ -247,6 +256,7 @@ fn test_cli_all_filtered_out() {
 
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .arg("-e")
         .arg(".*")  // Exclude everything
         .assert()
@@ -314,6 +324,7 @@ fn test_cli_complex_filtering_scenario() {
     // Exclude all drivers and arm, but include USB driver
     cargo_bin_cmd!("compdb-filter")
         .current_dir(temp_dir.path())
+        .arg("--in-place")
         .arg("-e")
         .arg("drivers/")
         .arg("-e")
@@ -348,3 +359,831 @@ fn test_cli_stats_output_format() {
         .success()
         .stderr(predicate::str::contains("Filtered: 4 -> 3 entries (1 removed)"));
 }
+
+#[test]
+fn test_cli_replaces_database_atomically() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+
+    let commands = sample_compile_db();
+    fs::write(&db_path, serde_json::to_string_pretty(&commands).unwrap()).unwrap();
+    fs::set_permissions(&db_path, fs::Permissions::from_mode(0o640)).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("--in-place")
+        .arg("-e")
+        .arg("^tests/")
+        .assert()
+        .success();
+
+    // Only the database, its backup and the lock file remain, no temporary files
+    let mut names: Vec<String> = fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["compile_commands.json", "compile_commands.json.bak", "compile_commands.json.lock"]
+    );
+    assert_eq!(fs::metadata(&db_path).unwrap().permissions().mode() & 0o777, 0o640);
+}
+
+#[test]
+fn test_cli_missing_file_leaves_no_lock() {
+    let temp_dir = TempDir::new().unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("nonexistent.json")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("nonexistent.json: no such file"));
+
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_cli_keeps_arguments_form_and_extra_fields() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+
+    let content = r#"[
+  {"directory":"/build","file":"src/main.c","arguments":["gcc","-c","src/main.c"],"output":"main.o"},
+  {"directory":"/build","file":"tests/test.c","arguments":["gcc","-c","tests/test.c"],"target":"x86_64"}
+]"#;
+    fs::write(&db_path, content).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("--in-place")
+        .arg("-e")
+        .arg("^tests/")
+        .assert()
+        .success();
+
+    let filtered: serde_json::Value = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(
+        filtered,
+        serde_json::json!([
+            {"directory":"/build","file":"src/main.c","arguments":["gcc","-c","src/main.c"],"output":"main.o"}
+        ])
+    );
+}
+
+#[test]
+fn test_cli_map_rewrites_paths_before_filtering() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+
+    let content = r#"[
+  {"directory":"/build/src","file":"/build/src/lib/../main.c","command":"gcc -I/build/src/include -c /build/src/main.c"},
+  {"directory":"/build/src","file":"/build/src/vendor/z.c","command":"gcc -isystem /build/src/vendor -c vendor/z.c"}
+]"#;
+    fs::write(&db_path, content).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("--in-place")
+        .args(["--map", "/build/src=/home/me/src", "-e", "^/home/me/src/vendor/"])
+        .assert()
+        .success();
+
+    let filtered: Vec<CompileCommand> = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(
+        filtered,
+        vec![CompileCommand {
+            command: "gcc -I/home/me/src/include -c /home/me/src/main.c".to_string(),
+            directory: "/home/me/src".to_string(),
+            file: "/home/me/src/main.c".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_cli_rejects_invalid_mapping() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    fs::write(&db_path, "[]").unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--map", "/build/src"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected FROM=TO"));
+}
+
+#[test]
+fn test_cli_export_and_instantiate_in_another_checkout() {
+    let temp_dir = TempDir::new().unwrap();
+    let checkout_a = temp_dir.path().join("a");
+    let checkout_b = temp_dir.path().join("b");
+    fs::create_dir_all(checkout_a.join(".git")).unwrap();
+    fs::create_dir_all(checkout_b.join(".git")).unwrap();
+    let a = checkout_a.to_str().unwrap();
+    let b = checkout_b.to_str().unwrap();
+
+    let db = |root: &str| {
+        vec![CompileCommand {
+            command: format!("gcc -I{root}/include -isystem /usr/include/glib -c {root}/src/main.c"),
+            directory: format!("{root}/build"),
+            file: format!("{root}/src/main.c"),
+        }]
+    };
+    fs::write(checkout_a.join("compile_commands.json"), serde_json::to_string(&db(a)).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(&checkout_a)
+        .arg("--in-place")
+        .arg("--export")
+        .assert()
+        .success();
+
+    let exported = fs::read_to_string(checkout_a.join("compile_commands.json")).unwrap();
+    assert!(!exported.contains(a));
+    let exported: Vec<CompileCommand> = serde_json::from_str(&exported).unwrap();
+    assert_eq!(exported[0].directory, "${COMPDB_ROOT}/build");
+    assert_eq!(
+        exported[0].command,
+        "gcc '-I${COMPDB_ROOT}/include' -isystem /usr/include/glib -c '${COMPDB_ROOT}/src/main.c'"
+    );
+
+    let db_b = checkout_b.join("compile_commands.json");
+    fs::copy(checkout_a.join("compile_commands.json"), &db_b).unwrap();
+    cargo_bin_cmd!("compdb-filter")
+        .arg("--in-place")
+        .arg(&db_b)
+        .arg("--instantiate")
+        .assert()
+        .success();
+
+    let instantiated: Vec<CompileCommand> = serde_json::from_str(&fs::read_to_string(&db_b).unwrap()).unwrap();
+    assert_eq!(instantiated, db(b));
+}
+
+#[test]
+fn test_cli_export_relative_with_root() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+
+    let content = r#"[
+  {"directory":"/home/me/proj/build","file":"/home/me/proj/src/main.c","arguments":["gcc","-I/home/me/proj/include","-c","/home/me/proj/src/main.c"]}
+]"#;
+    fs::write(&db_path, content).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("--in-place")
+        .args(["--export", "--relative", "--root", "/home/me/proj"])
+        .assert()
+        .success();
+
+    let exported: serde_json::Value = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(
+        exported,
+        serde_json::json!([
+            {"directory":"build","file":"../src/main.c","arguments":["gcc","-I../include","-c","../src/main.c"]}
+        ])
+    );
+}
+
+#[test]
+fn test_cli_export_requires_root_outside_checkout() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    fs::write(&db_path, "[]").unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("--export")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("could not detect project root"));
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--export", "--instantiate", "--root", "/x"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_cli_gcc_compat_rewrites_flags() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    let rules_path = temp_dir.path().join("rules.txt");
+
+    let content = r#"[
+  {"directory":"/build","file":"a.c","command":"gcc -fconserve-stack -Wno-maybe-uninitialized -march=native -mfoo -c a.c"},
+  {"directory":"/build","file":"b.c","arguments":["gcc","-fplugin=x.so","-include","pch.h.gch","-c","b.c"]}
+]"#;
+    fs::write(&db_path, content).unwrap();
+    fs::write(&rules_path, "# ours\n-mfoo => -DFOO\n").unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .arg("--in-place")
+        .args(["--compat-rule", "-march=*", "--compat-rules", "rules.txt"])
+        .assert()
+        .success();
+
+    let filtered: serde_json::Value = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(
+        filtered,
+        serde_json::json!([
+            {"directory":"/build","file":"a.c","command":"gcc -DFOO -c a.c"},
+            {"directory":"/build","file":"b.c","arguments":["gcc","-include","pch.h","-c","b.c"]}
+        ])
+    );
+}
+
+#[test]
+fn test_cli_output_file_leaves_input_untouched() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    let original = serde_json::to_string_pretty(&sample_compile_db()).unwrap();
+    fs::write(&db_path, &original).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["-e", "^tests/", "--output", "filtered.json"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Filtered: 4 -> 3 entries"))
+        .stderr(predicate::str::contains("Backup").not());
+
+    assert_eq!(fs::read_to_string(&db_path).unwrap(), original);
+    assert!(!temp_dir.path().join("compile_commands.json.bak").exists());
+    let filtered: Vec<CompileCommand> =
+        serde_json::from_str(&fs::read_to_string(temp_dir.path().join("filtered.json")).unwrap()).unwrap();
+    assert_eq!(filtered.len(), 3);
+}
+
+#[test]
+fn test_cli_reads_stdin_and_writes_stdout() {
+    let temp_dir = TempDir::new().unwrap();
+    let output = cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["-", "-e", "^vendor/"])
+        .write_stdin(serde_json::to_string(&sample_compile_db()).unwrap())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let filtered: Vec<CompileCommand> = serde_json::from_slice(&output).unwrap();
+    assert_eq!(filtered.len(), 3);
+    assert!(filtered.iter().all(|c| !c.file.starts_with("vendor/")));
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_cli_output_dash_writes_stdout() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    fs::write(&db_path, serde_json::to_string(&sample_compile_db()).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["-e", "^src/", "-o", "-"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tests/test.c"))
+        .stdout(predicate::str::contains("src/main.c").not());
+
+    assert!(!temp_dir.path().join("compile_commands.json.bak").exists());
+}
+
+#[test]
+fn test_cli_dry_run_lists_removed_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    let original = serde_json::to_string(&sample_compile_db()).unwrap();
+    fs::write(&db_path, &original).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--dry-run", "-e", "^(tests|vendor)/"])
+        .assert()
+        .success()
+        .stdout("tests/test.c\nvendor/lib.c\n")
+        .stderr(predicate::str::contains("Dry run: 4 -> 2 entries (2 removed)"));
+
+    assert_eq!(fs::read_to_string(&db_path).unwrap(), original);
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_cli_explicit_in_place() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    fs::write(&db_path, serde_json::to_string(&sample_compile_db()).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--in-place", "-e", "^tests/"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Backup created"));
+
+    let filtered: Vec<CompileCommand> = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(filtered.len(), 3);
+
+    cargo_bin_cmd!("compdb-filter")
+        .args(["-", "--in-place"])
+        .write_stdin("[]")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot edit stdin in place"));
+}
+
+#[test]
+fn test_cli_matches_directory_compiler_flags_and_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+
+    let content = r#"[
+  {"directory":"/build","file":"a.c","command":"gcc -c a.c -o a.o"},
+  {"directory":"/build","file":"b.c","command":"gcc -DUNIT_TEST -c b.c"},
+  {"directory":"/build/tools","file":"c.c","command":"gcc -c c.c"},
+  {"directory":"/build","file":"d.c","command":"aarch64-linux-gnu-gcc -c d.c"},
+  {"directory":"/build","file":"e.c","arguments":["gcc","-c","e.c"],"output":"host/e.o"},
+  {"directory":"/build","file":"f.c","command":"gcc -DUNIT_TEST -DKEEP -c f.c"}
+]"#;
+    fs::write(&db_path, content).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args([
+            "--dry-run", "-e", "flag:-DUNIT_TEST", "-e", "directory:/tools$", "-e", "compiler:aarch64", "-e",
+            "output:^host/", "-i", "flag:-D=^KEEP$",
+        ])
+        .assert()
+        .success()
+        .stdout("b.c\nc.c\nd.c\ne.c\n");
+}
+
+#[test]
+fn test_cli_ordered_rules_first_match_wins() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    let files = ["drivers/net/e1000.c", "drivers/gpu/drm.c", "drivers/gpu/nouveau/nv50.c", "kernel/fork.c", "tests/t.c"];
+    let db: Vec<CompileCommand> = files
+        .iter()
+        .map(|f| CompileCommand { command: format!("gcc -c {f}"), directory: "/build".to_string(), file: f.to_string() })
+        .collect();
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--dry-run", "-r", "- ^drivers/gpu/nouveau/", "-r", "+ ^drivers/gpu/", "--rule", "- ^drivers/", "-e", "^tests/"])
+        .assert()
+        .success()
+        .stdout("drivers/net/e1000.c\ndrivers/gpu/nouveau/nv50.c\ntests/t.c\n");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--explain", "drivers/gpu/drm.c", "-r", "- ^drivers/gpu/nouveau/", "-r", "+ ^drivers/gpu/", "-r", "- ^drivers/"])
+        .assert()
+        .success()
+        .stdout("drivers/gpu/drm.c (/build): kept by rule 2: + ^drivers/gpu/\n");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--explain", "/build/tests/t.c", "-e", "^tests/"])
+        .assert()
+        .success()
+        .stdout("tests/t.c (/build): excluded by --exclude ^tests/\n");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--explain", "missing.c"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no entry for missing.c"));
+}
+
+#[test]
+fn test_cli_glob_and_gitignore_patterns_relative_to_root() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().join("proj");
+    fs::create_dir_all(&root).unwrap();
+    let db_path = root.join("compile_commands.json");
+    let root_str = root.to_str().unwrap();
+    let files = ["src/main.c", "src/test/unit.c", "third_party/zlib/inflate.c", "third_party/zlib/test/t.c"];
+    let db: Vec<CompileCommand> = files
+        .iter()
+        .map(|f| CompileCommand {
+            command: format!("gcc -c {root_str}/{f}"),
+            directory: format!("{root_str}/build"),
+            file: format!("{root_str}/{f}"),
+        })
+        .collect();
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .args(["--dry-run", "--syntax", "glob", "--match-path", "root", "--root", root_str])
+        .args(["-e", "**/test/**", "-e", "third_party/**/*.c"])
+        .arg(&db_path)
+        .assert()
+        .success()
+        .stdout(format!(
+            "{root_str}/src/test/unit.c\n{root_str}/third_party/zlib/inflate.c\n{root_str}/third_party/zlib/test/t.c\n"
+        ));
+
+    cargo_bin_cmd!("compdb-filter")
+        .args(["--dry-run", "--syntax", "gitignore", "--match-path", "root", "--root", root_str])
+        .args(["-e", "third_party/", "-e", "!zlib/", "-e", "test/"])
+        .arg(&db_path)
+        .assert()
+        .success()
+        .stdout(format!("{root_str}/src/test/unit.c\n{root_str}/third_party/zlib/test/t.c\n"));
+
+    cargo_bin_cmd!("compdb-filter")
+        .args(["--dry-run", "--syntax", "gitignore", "-r", "- !test/"])
+        .arg(&db_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("instead of negating"));
+}
+
+#[test]
+fn test_cli_matches_normalized_absolute_and_root_relative_paths() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    let db_path = root.join("compile_commands.json");
+    let root_str = root.to_str().unwrap();
+    // The same kind of file written absolute, relative and with `..`, as different generators do
+    let db = vec![
+        CompileCommand {
+            command: "cc -c a.c".to_string(),
+            directory: format!("{root_str}/build"),
+            file: format!("{root_str}/src/gen/a.c"),
+        },
+        CompileCommand { command: "cc -c b.c".to_string(), directory: format!("{root_str}/build"), file: "../src/gen/b.c".to_string() },
+        CompileCommand { command: "cc -c c.c".to_string(), directory: root_str.to_string(), file: "./src/gen/../c.c".to_string() },
+    ];
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .args(["--dry-run", "--match-path", "root", "--root", root_str, "-e", "^src/gen/"])
+        .arg(&db_path)
+        .assert()
+        .success()
+        .stdout(format!("{root_str}/src/gen/a.c\n../src/gen/b.c\n"));
+
+    cargo_bin_cmd!("compdb-filter")
+        .args(["--dry-run", "--match-path", "absolute", "-e"])
+        .arg(format!("^{}/src/[^/]*$", regex::escape(root_str)))
+        .arg(&db_path)
+        .assert()
+        .success()
+        .stdout("./src/gen/../c.c\n");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(root.join(".."))
+        .args(["--match-path", "root", "--root", root_str, "-e", "^src/gen/", "--explain"])
+        .arg(root.join("src/gen/b.c"))
+        .arg(&db_path)
+        .assert()
+        .success()
+        .stdout(format!("../src/gen/b.c ({root_str}/build) as src/gen/b.c: excluded by --exclude ^src/gen/\n"));
+}
+
+#[test]
+fn test_cli_profiles_and_pattern_files_with_cli_on_top() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    let files = ["src/main.c", "src/test/unit.c", "third_party/zlib/inflate.c", "gen/parser.c", "tools/gen.c"];
+    let db: Vec<CompileCommand> = files
+        .iter()
+        .map(|f| CompileCommand { command: format!("gcc -c {f}"), directory: "/build".to_string(), file: f.to_string() })
+        .collect();
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+    fs::write(
+        temp_dir.path().join(".compdb.toml"),
+        r#"
+[profiles.no-tests]
+syntax = "gitignore"
+exclude = ["test/"]
+
+[profiles.ours-only]
+exclude = ["^third_party/", "^gen/"]
+"#,
+    )
+    .unwrap();
+    fs::write(temp_dir.path().join("keep.txt"), "# generated, but checked in\n^gen/parser\\.c$\n").unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--dry-run", "--profile", "no-tests", "-p", "ours-only", "--include-from", "keep.txt"])
+        .assert()
+        .success()
+        .stdout("src/test/unit.c\nthird_party/zlib/inflate.c\n");
+
+    // Command-line rules take precedence over the profile's patterns
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--dry-run", "-p", "ours-only", "-r", "+ ^third_party/", "-e", "^tools/"])
+        .assert()
+        .success()
+        .stdout("gen/parser.c\ntools/gen.c\n");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(temp_dir.path())
+        .args(["--dry-run", "-p", "kernel-x86"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("kernel-x86").and(predicate::str::contains("no-tests, ours-only")));
+}
+
+#[test]
+fn test_cli_keeps_listed_and_git_changed_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let dir_str = dir.to_str().unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    };
+    fs::create_dir_all(dir.join("src")).unwrap();
+    for file in ["src/a.c", "src/b.c", "src/c.c", "src/util.h"] {
+        fs::write(dir.join(file), "int x;\n").unwrap();
+    }
+    // Only a.c has dependency information
+    fs::write(dir.join("a.d"), "a.o: src/a.c src/util.h\n").unwrap();
+    let db: Vec<CompileCommand> = ["a", "b", "c"]
+        .iter()
+        .map(|f| CompileCommand {
+            command: format!("gcc -MMD -c src/{f}.c -o {f}.o"),
+            directory: dir_str.to_string(),
+            file: format!("src/{f}.c"),
+        })
+        .collect();
+    let db_path = dir.join("compile_commands.json");
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-qm", "initial"]);
+
+    let kept = |args: &[&str], expected: &str| {
+        cargo_bin_cmd!("compdb-filter").current_dir(dir).args(args).args(["-o", "-"]).assert().success().stdout(
+            predicate::function(move |out: &str| {
+                let db: Vec<CompileCommand> = serde_json::from_str(out).unwrap();
+                db.iter().map(|c| c.file.as_str()).collect::<Vec<_>>().join(" ") == expected
+            }),
+        );
+    };
+
+    fs::write(dir.join("files.txt"), "src/c.c\n\n").unwrap();
+    kept(&["--files-from", "files.txt"], "src/c.c");
+
+    fs::write(dir.join("src/b.c"), "int y;\n").unwrap();
+    kept(&["--git-worktree"], "src/b.c");
+    kept(&["--git-staged"], "");
+    git(&["add", "src/b.c"]);
+    kept(&["--git-staged", "--files-from", "files.txt"], "src/b.c src/c.c");
+    git(&["commit", "-qm", "change b"]);
+    kept(&["--git-diff", "HEAD~1..HEAD"], "src/b.c");
+    let output = dir.join("diff.out");
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(dir)
+        .arg(format!("--git-diff=--output={}", output.display()))
+        .args(["-o", "-"])
+        .assert()
+        .failure();
+    assert!(!output.exists());
+
+    fs::write(dir.join("src/util.h"), "int z;\n").unwrap();
+    kept(&["--git-worktree"], "");
+    kept(&["--git-worktree", "--with-dependents"], "src/a.c");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(dir)
+        .args(["--git-worktree", "--explain", "src/c.c"])
+        .assert()
+        .success()
+        .stdout("src/c.c (".to_string() + dir_str + "): excluded: not a listed or changed file\n");
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(dir)
+        .args(["--with-dependents", "-o", "-"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_cli_runs_git_in_checkout_for_out_of_tree_build() {
+    let temp_dir = TempDir::new().unwrap();
+    let checkout = temp_dir.path().join("src");
+    let build = temp_dir.path().join("build");
+    fs::create_dir_all(&checkout).unwrap();
+    fs::create_dir_all(&build).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .current_dir(&checkout)
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    };
+    fs::write(checkout.join("a.c"), "int x;\n").unwrap();
+    fs::write(checkout.join("b.c"), "int x;\n").unwrap();
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-qm", "initial"]);
+    fs::write(checkout.join("b.c"), "int y;\n").unwrap();
+    let db: Vec<CompileCommand> = ["a", "b"]
+        .iter()
+        .map(|f| CompileCommand {
+            command: format!("gcc -c {}/{f}.c", checkout.display()),
+            directory: build.to_str().unwrap().to_string(),
+            file: format!("{}/{f}.c", checkout.display()),
+        })
+        .collect();
+    let db_path = build.join("compile_commands.json");
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+
+    for (dir, root) in [(&checkout, None), (&build, Some(&checkout))] {
+        cargo_bin_cmd!("compdb-filter")
+            .current_dir(dir)
+            .arg(&db_path)
+            .args(root.map(|root| format!("--root={}", root.display())))
+            .args(["--git-worktree", "--dry-run"])
+            .assert()
+            .success()
+            .stdout(format!("{}/a.c\n", checkout.display()));
+    }
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(&build)
+        .args(["--git-worktree", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("rev-parse"));
+}
+
+#[test]
+fn test_cli_drops_stale_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let dir_str = dir.to_str().unwrap();
+    fs::write(dir.join("a.c"), "").unwrap();
+    fs::write(dir.join("c.c"), "").unwrap();
+    let db_path = dir.join("compile_commands.json");
+    let db = vec![
+        CompileCommand { command: "gcc -c a.c".to_string(), directory: dir_str.to_string(), file: "a.c".to_string() },
+        CompileCommand { command: "gcc -c b.c".to_string(), directory: dir_str.to_string(), file: "b.c".to_string() },
+        CompileCommand {
+            command: "gcc -c ../a.c".to_string(),
+            directory: format!("{dir_str}/out"),
+            file: "../a.c".to_string(),
+        },
+        CompileCommand { command: "gcc -Igone -c c.c".to_string(), directory: dir_str.to_string(), file: "c.c".to_string() },
+    ];
+    fs::write(&db_path, serde_json::to_string(&db).unwrap()).unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(dir)
+        .args(["--dry-run", "--drop-stale"])
+        .assert()
+        .success()
+        .stdout("b.c\n../a.c\n")
+        .stderr(
+            predicate::str::contains("Stale: b.c (".to_string() + dir_str + "): file does not exist")
+                .and(predicate::str::contains("): directory does not exist")),
+        );
+
+    cargo_bin_cmd!("compdb-filter")
+        .current_dir(dir)
+        .args(["--dry-run", "--drop-stale", "--stale-includes"])
+        .assert()
+        .success()
+        .stdout("b.c\n../a.c\nc.c\n")
+        .stderr(predicate::str::contains("no include directory exists"));
+}
+
+#[test]
+fn test_cli_backup_options_list_and_restore() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    fs::write(&db_path, serde_json::to_string(&sample_compile_db()).unwrap()).unwrap();
+    let backup_count = || {
+        fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().contains(".bak"))
+            .count()
+    };
+
+    // Each edit removes one more entry, keeping only the two most recent backups
+    for pattern in ["^vendor/", "^tests/", "util"] {
+        cargo_bin_cmd!("compdb-filter")
+            .arg(&db_path)
+            .args(["--in-place", "--keep-backups", "2", "-e", pattern])
+            .assert()
+            .success();
+    }
+    assert_eq!(backup_count(), 2);
+
+    cargo_bin_cmd!("compdb-filter").arg(&db_path).args(["--in-place", "--no-backup"]).assert().success();
+    assert_eq!(backup_count(), 2);
+
+    // A failed run neither backs up nor rotates out an older backup
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["--in-place", "--keep-backups", "1", "--compat-rules", "missing.rules"])
+        .assert()
+        .failure();
+    assert_eq!(backup_count(), 2);
+
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .arg("--list-backups")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"(?m)^  1  \d{4}-\d\d-\d\d \d\d:\d\d:\d\d     2 entries  .*\.bak").unwrap())
+        .stdout(predicate::str::is_match(r"(?m)^  2  .*     3 entries  ").unwrap());
+
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["--restore=2"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("3 entries"));
+    let restored: Vec<CompileCommand> = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(restored.len(), 3);
+
+    // The replaced database took the backup's place, as the most recent backup
+    cargo_bin_cmd!("compdb-filter").arg(&db_path).arg("--restore").assert().success();
+    let restored: Vec<CompileCommand> = serde_json::from_str(&fs::read_to_string(&db_path).unwrap()).unwrap();
+    assert_eq!(restored.len(), 1);
+
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["--restore", "5"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has 2 backups, not 5"));
+
+    let backup_dir = temp_dir.path().join("backups");
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["--in-place", "--backup-dir"])
+        .arg(&backup_dir)
+        .assert()
+        .success();
+    // Named after the database's path, since other projects may share the directory
+    let names: Vec<String> =
+        fs::read_dir(&backup_dir).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+    assert_eq!(names.len(), 1);
+    assert!(regex::Regex::new(r"^compile_commands\.json\.[0-9a-f]{8}\.bak$").unwrap().is_match(&names[0]), "{:?}", names);
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["--list-backups", "--backup-dir"])
+        .arg(&backup_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(&names[0]));
+}
+
+#[test]
+fn test_cli_edits_flags_of_scoped_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let db_path = temp_dir.path().join("compile_commands.json");
+    let content = r#"[
+  {"directory":"/build","file":"src/a.c","command":"gcc -std=gnu89 -Werror -Werror=format -flto -I inc -c src/a.c"},
+  {"directory":"/build","file":"legacy/b.c","arguments":["gcc","-std=gnu89","-Werror","-c","legacy/b.c"]}
+]"#;
+    fs::write(&db_path, content).unwrap();
+    fs::write(temp_dir.path().join("edits"), "# for clangd\nremove -Werror=*\nreplace -I => -isystem\n").unwrap();
+
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["-o", "-", "--flag-edits"])
+        .arg(temp_dir.path().join("edits"))
+        .args(["--remove-flag", "-Werror", "--remove-flag", "/^-flto/", "--replace-flag", "-std=gnu89 => -std=gnu11"])
+        .args(["--add-flag", "-Wno-unknown-warning-option", "--edit-only", "^src/"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(r#""command": "gcc -std=gnu11 -isystem inc -c src/a.c -Wno-unknown-warning-option""#)
+                .and(predicate::str::contains(r#""-Werror","#)),
+        );
+
+    cargo_bin_cmd!("compdb-filter")
+        .arg(&db_path)
+        .args(["-o", "-", "--replace-flag", "-O2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid flag edit"));
+}